@@ -1,4 +1,5 @@
-use std::{fmt::Pointer, rc::Rc};
+use regex::Regex;
+use std::{cell::RefCell, fmt::Pointer, rc::Rc};
 
 #[derive(Clone)]
 pub struct AstNode {
@@ -13,6 +14,22 @@ impl core::fmt::Debug for AstNode {
 
 struct InnerVal {
     node_type: AstNodeType,
+    span: Option<Span>,
+    /// The last regex compiled from what this node evaluated to.
+    regex: RefCell<Option<(String, Regex)>>,
+}
+
+/// Byte range of a node in the parsed source text.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
 }
 
 #[derive(Debug)]
@@ -20,8 +37,10 @@ pub enum AstNodeType {
     Null,
     SubString(String),
     Identifier(String),
-    Integer(u64),
-    Float64(f64),
+    Integer(i64),
+    /// A number that isn't an i64, along with the digits as written so that
+    /// it can be read exactly in decimal mode.
+    Float64(f64, String),
     Bool(bool),
     Pipe(AstNode, AstNode),
     Dot,
@@ -39,6 +58,9 @@ pub enum AstNodeType {
     Subtract(AstNode, AstNode),
     Multiply(AstNode, AstNode),
     Divide(AstNode, AstNode),
+    Modulo(AstNode, AstNode),
+    FloorDivide(AstNode, AstNode),
+    Power(AstNode, AstNode),
     Negative(AstNode),
     FcnCall {
         name: AstNode,
@@ -62,6 +84,15 @@ pub enum AstNodeType {
         identifier: AstNode,
         expr: AstNode,
     },
+    FcnDef {
+        name: AstNode,
+        params: Option<AstNode>,
+        body: AstNode,
+    },
+    Import {
+        path: AstNode,
+        alias: Option<AstNode>,
+    },
 
     // Types
     NumberType,
@@ -78,14 +109,85 @@ pub enum AstNodeType {
 impl AstNode {
     pub fn new(node_type: AstNodeType) -> AstNode {
         AstNode {
-            inner_val: Rc::new(InnerVal { node_type }),
+            inner_val: Rc::new(InnerVal {
+                node_type,
+                span: None,
+                regex: RefCell::new(None),
+            }),
+        }
+    }
+
+    pub fn new_with_span(node_type: AstNodeType, span: Span) -> AstNode {
+        AstNode {
+            inner_val: Rc::new(InnerVal {
+                node_type,
+                span: Some(span),
+                regex: RefCell::new(None),
+            }),
         }
     }
 
+    /// Creates a node spanning from the start of `first` to the end of `last`.
+    pub fn new_spanning(node_type: AstNodeType, first: &AstNode, last: &AstNode) -> AstNode {
+        match (first.get_span(), last.get_span()) {
+            (Some(first), Some(last)) => {
+                AstNode::new_with_span(node_type, Span::new(first.start, last.end))
+            }
+            _ => AstNode::new(node_type),
+        }
+    }
+
+    /// Compiles a regex for a pattern this node evaluated to. The last regex
+    /// is reused while the pattern stays the same, so `map(test("..."))`
+    /// only compiles once.
+    pub fn get_regex(&self, pattern: &str) -> Result<Regex, regex::Error> {
+        let mut cache = self.inner_val.regex.borrow_mut();
+        if let Some((cached_pattern, regex)) = cache.as_ref() {
+            if cached_pattern == pattern {
+                return Ok(regex.clone());
+            }
+        }
+        let regex = Regex::new(pattern)?;
+        *cache = Some((pattern.to_string(), regex.clone()));
+        Ok(regex)
+    }
+
     pub fn get_type(&self) -> &AstNodeType {
         &self.inner_val.node_type
     }
 
+    pub fn get_span(&self) -> Option<Span> {
+        self.inner_val.span
+    }
+
+    /// Short name of the operation used in error call traces.
+    pub fn get_op_name(&self) -> Option<&str> {
+        match self.get_type() {
+            AstNodeType::FcnCall { name, .. } => match name.get_type() {
+                AstNodeType::Identifier(name) => Some(name.as_str()),
+                _ => None,
+            },
+            AstNodeType::Equals(_, _) => Some("=="),
+            AstNodeType::NotEquals(_, _) => Some("!="),
+            AstNodeType::LessThan(_, _) => Some("<"),
+            AstNodeType::LessThanOrEqual(_, _) => Some("<="),
+            AstNodeType::GreaterThan(_, _) => Some(">"),
+            AstNodeType::GreaterThanOrEqual(_, _) => Some(">="),
+            AstNodeType::Or(_, _) => Some("or"),
+            AstNodeType::And(_, _) => Some("and"),
+            AstNodeType::Not(_) => Some("not"),
+            AstNodeType::Add(_, _) => Some("+"),
+            AstNodeType::Subtract(_, _) => Some("-"),
+            AstNodeType::Multiply(_, _) => Some("*"),
+            AstNodeType::Divide(_, _) => Some("/"),
+            AstNodeType::Modulo(_, _) => Some("%"),
+            AstNodeType::FloorDivide(_, _) => Some("//"),
+            AstNodeType::Power(_, _) => Some("**"),
+            AstNodeType::Negative(_) => Some("-"),
+            _ => None,
+        }
+    }
+
     pub fn new_fcn_call(name: &str, args: &[&AstNode]) -> AstNode {
         let mut args_node: Option<AstNode> = None;
         for arg in args {
@@ -0,0 +1,268 @@
+use crate::val::{OrderedMap, Val};
+use std::io::{BufRead, BufReader, Read};
+
+/// Deepest nesting of lists and maps, the same limit serde_json has.
+const MAX_DEPTH: usize = 128;
+
+/// Reads a stream of JSON values for decimal mode, parsing every number from
+/// the text it was written as with `Val::parse_number()`. serde_json can only
+/// keep that text with its `arbitrary_precision` feature, which would slow
+/// down reading JSON outside of decimal mode too.
+pub struct DecimalJsonReader<R: Read> {
+    reader: BufReader<R>,
+    line: usize,
+    column: usize,
+}
+
+impl<R: Read> DecimalJsonReader<R> {
+    pub fn new(reader: R) -> Self {
+        DecimalJsonReader {
+            reader: BufReader::new(reader),
+            line: 1,
+            column: 1,
+        }
+    }
+
+    /// Reads a single value that makes up the whole input.
+    pub fn read_single(mut self) -> Result<Val, String> {
+        let val = match self.next() {
+            None => return Err(self.err("EOF while parsing a value")),
+            Some(val) => val?,
+        };
+        self.skip_ws()?;
+        match self.peek()? {
+            None => Ok(val),
+            Some(_) => Err(self.err("trailing characters")),
+        }
+    }
+
+    fn err(&self, msg: &str) -> String {
+        format!("{} at line {} column {}", msg, self.line, self.column)
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>, String> {
+        match self.reader.fill_buf() {
+            Err(err) => Err(err.to_string()),
+            Ok(buf) => Ok(buf.first().copied()),
+        }
+    }
+
+    fn next_byte(&mut self) -> Result<Option<u8>, String> {
+        let byte = self.peek()?;
+        if let Some(byte) = byte {
+            self.reader.consume(1);
+            if byte == b'\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+        Ok(byte)
+    }
+
+    fn skip_ws(&mut self) -> Result<(), String> {
+        while let Some(b' ' | b'\n' | b'\r' | b'\t') = self.peek()? {
+            self.next_byte()?;
+        }
+        Ok(())
+    }
+
+    fn expect_literal(&mut self, literal: &str) -> Result<(), String> {
+        for expected in literal.bytes() {
+            if self.next_byte()? != Some(expected) {
+                return Err(self.err("expected value"));
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_value(&mut self, depth: usize) -> Result<Val, String> {
+        if depth > MAX_DEPTH {
+            return Err(self.err("recursion limit exceeded"));
+        }
+        self.skip_ws()?;
+        match self.peek()? {
+            None => Err(self.err("EOF while parsing a value")),
+            Some(b'{') => self.parse_map(depth),
+            Some(b'[') => self.parse_list(depth),
+            Some(b'"') => Ok(Val::new_str(self.parse_str()?.as_str())),
+            Some(b't') => self.expect_literal("true").map(|_| Val::new_bool(true)),
+            Some(b'f') => self.expect_literal("false").map(|_| Val::new_bool(false)),
+            Some(b'n') => self.expect_literal("null").map(|_| Val::new_null()),
+            Some(b'-' | b'0'..=b'9') => self.parse_number(),
+            Some(_) => Err(self.err("expected value")),
+        }
+    }
+
+    fn parse_list(&mut self, depth: usize) -> Result<Val, String> {
+        self.next_byte()?;
+        let mut vals = Vec::<Val>::new();
+        self.skip_ws()?;
+        if self.peek()? == Some(b']') {
+            self.next_byte()?;
+            return Ok(Val::new_list(vals));
+        }
+        loop {
+            vals.push(self.parse_value(depth + 1)?);
+            self.skip_ws()?;
+            match self.next_byte()? {
+                Some(b',') => {}
+                Some(b']') => return Ok(Val::new_list(vals)),
+                None => return Err(self.err("EOF while parsing a list")),
+                Some(_) => return Err(self.err("expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn parse_map(&mut self, depth: usize) -> Result<Val, String> {
+        self.next_byte()?;
+        let mut map = OrderedMap::new();
+        self.skip_ws()?;
+        if self.peek()? == Some(b'}') {
+            self.next_byte()?;
+            return Ok(Val::new_map(map));
+        }
+        loop {
+            self.skip_ws()?;
+            let key = match self.peek()? {
+                Some(b'"') => Val::new_str(self.parse_str()?.as_str()),
+                None => return Err(self.err("EOF while parsing an object")),
+                Some(_) => return Err(self.err("key must be a string")),
+            };
+            self.skip_ws()?;
+            if self.next_byte()? != Some(b':') {
+                return Err(self.err("expected `:`"));
+            }
+            let val = self.parse_value(depth + 1)?;
+            map.insert(&key, &val);
+            self.skip_ws()?;
+            match self.next_byte()? {
+                Some(b',') => {}
+                Some(b'}') => return Ok(Val::new_map(map)),
+                None => return Err(self.err("EOF while parsing an object")),
+                Some(_) => return Err(self.err("expected `,` or `}`")),
+            }
+        }
+    }
+
+    fn parse_str(&mut self) -> Result<String, String> {
+        self.next_byte()?;
+        let mut bytes = Vec::<u8>::new();
+        loop {
+            match self.next_byte()? {
+                None => return Err(self.err("EOF while parsing a string")),
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    let escaped = match self.next_byte()? {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => self.parse_unicode_escape()?,
+                        _ => return Err(self.err("invalid escape")),
+                    };
+                    bytes.extend(escaped.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                Some(byte) if byte < 0x20 => {
+                    return Err(self.err("control character found while parsing a string"))
+                }
+                Some(byte) => bytes.push(byte),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.err("invalid unicode in string"))
+    }
+
+    /// Parses the hex digits after `\u`, along with the second half of a
+    /// surrogate pair.
+    fn parse_unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.parse_hex4()?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| self.err("lone trailing surrogate"));
+        }
+        if self.next_byte()? != Some(b'\\') || self.next_byte()? != Some(b'u') {
+            return Err(self.err("lone leading surrogate"));
+        }
+        let low = self.parse_hex4()?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return Err(self.err("lone leading surrogate"));
+        }
+        let code = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+        char::from_u32(code).ok_or_else(|| self.err("invalid unicode escape"))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self
+                .next_byte()?
+                .and_then(|byte| (byte as char).to_digit(16))
+                .ok_or_else(|| self.err("invalid unicode escape"))?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn parse_number(&mut self) -> Result<Val, String> {
+        let mut text = String::new();
+        if self.peek()? == Some(b'-') {
+            self.next_byte()?;
+            text.push('-');
+        }
+        let int_start = text.len();
+        self.push_digits(&mut text)?;
+        let int_digits = &text[int_start..];
+        if int_digits.is_empty() || (int_digits.len() > 1 && int_digits.starts_with('0')) {
+            return Err(self.err("invalid number"));
+        }
+        if self.peek()? == Some(b'.') {
+            self.next_byte()?;
+            text.push('.');
+            if self.push_digits(&mut text)? == 0 {
+                return Err(self.err("invalid number"));
+            }
+        }
+        if let Some(b'e' | b'E') = self.peek()? {
+            self.next_byte()?;
+            text.push('e');
+            if let Some(sign @ (b'+' | b'-')) = self.peek()? {
+                self.next_byte()?;
+                text.push(sign as char);
+            }
+            if self.push_digits(&mut text)? == 0 {
+                return Err(self.err("invalid number"));
+            }
+        }
+        Val::parse_number(text.as_str(), true).ok_or_else(|| self.err("invalid number"))
+    }
+
+    fn push_digits(&mut self, text: &mut String) -> Result<usize, String> {
+        let mut count = 0;
+        while let Some(digit @ b'0'..=b'9') = self.peek()? {
+            self.next_byte()?;
+            text.push(digit as char);
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+impl<R: Read> Iterator for DecimalJsonReader<R> {
+    type Item = Result<Val, String>;
+
+    /// Reads the next value, or returns None at the end of the input.
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(err) = self.skip_ws() {
+            return Some(Err(err));
+        }
+        match self.peek() {
+            Err(err) => Some(Err(err)),
+            Ok(None) => None,
+            Ok(Some(_)) => Some(self.parse_value(0)),
+        }
+    }
+}
@@ -0,0 +1,76 @@
+/// Returns the 1-based line and column of the byte offset `idx` in `text`.
+pub fn line_col(text: &str, idx: usize) -> (usize, usize) {
+    let idx = floor_char_boundary(text, idx);
+    let before = &text[..idx];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |pos| pos + 1);
+    let col = text[line_start..idx].chars().count() + 1;
+    (line, col)
+}
+
+/// Renders the source lines touched by `markers` with each marker's byte
+/// range underlined by its character, e.g.
+///
+/// ```text
+///   |
+/// 1 | [1, 2] | map(. + 1
+///   |             -      ^
+/// ```
+pub fn render_snippet(text: &str, markers: &[(usize, usize, char)]) -> String {
+    let mut lines = markers
+        .iter()
+        .map(|(start, _, _)| line_col(text, *start).0)
+        .collect::<Vec<_>>();
+    lines.sort();
+    lines.dedup();
+
+    let gutter_width = lines.last().map_or(1, |line| line.to_string().len());
+    let source_lines = text.split('\n').collect::<Vec<_>>();
+
+    let mut result = format!("{} |\n", " ".repeat(gutter_width));
+    for line in lines {
+        let source_line = source_lines.get(line - 1).copied().unwrap_or("");
+        let source_line = source_line.trim_end_matches('\r').replace('\t', " ");
+        result.push_str(
+            format!("{:>width$} | {}\n", line, source_line, width = gutter_width).as_str(),
+        );
+
+        let mut underline = Vec::<char>::new();
+        for (start, end, marker) in markers {
+            let (start_line, start_col) = line_col(text, *start);
+            if start_line != line {
+                continue;
+            }
+            let (end_line, end_col) = line_col(text, *end);
+            let end_col = if end_line == line {
+                end_col.max(start_col + 1)
+            } else {
+                source_line.chars().count().max(start_col) + 1
+            };
+            if underline.len() < end_col - 1 {
+                underline.resize(end_col - 1, ' ');
+            }
+            for ch in &mut underline[start_col - 1..end_col - 1] {
+                *ch = *marker;
+            }
+        }
+        result.push_str(
+            format!(
+                "{} | {}\n",
+                " ".repeat(gutter_width),
+                underline.iter().collect::<String>()
+            )
+            .as_str(),
+        );
+    }
+
+    result
+}
+
+fn floor_char_boundary(text: &str, idx: usize) -> usize {
+    let mut idx = idx.min(text.len());
+    while !text.is_char_boundary(idx) {
+        idx -= 1;
+    }
+    idx
+}
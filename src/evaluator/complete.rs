@@ -0,0 +1,92 @@
+use super::eval_fcn::BUILTIN_FCNS;
+use super::EvalCtx;
+
+use crate::parser::KEYWORDS;
+use crate::val::{Val, ValType};
+
+impl EvalCtx {
+    /// Completions for the word that ends at byte offset `pos` in `line`.
+    /// Returns the offset where the word starts along with the replacements
+    /// for it. After a `.` the keys of the map at that path in the current
+    /// value are completed, otherwise variables, functions and keywords.
+    pub fn completions(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let before = &line[..pos];
+        let word_start = before
+            .rfind(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_' || ch == ':'))
+            .map_or(0, |idx| idx + 1);
+        let word = &before[word_start..];
+        if word.starts_with(|ch: char| ch.is_ascii_digit()) {
+            return (word_start, Vec::new());
+        }
+
+        let mut candidates = if before[..word_start].ends_with('.') {
+            self.key_completions(&before[..word_start - 1])
+        } else {
+            let mut candidates = self.variables.keys().cloned().collect::<Vec<_>>();
+            candidates.extend(KEYWORDS.iter().map(|keyword| keyword.to_string()));
+            candidates.extend(self.functions.keys().map(|name| format!("{}(", name)));
+            candidates.extend(BUILTIN_FCNS.iter().map(|(name, _)| format!("{}(", name)));
+            candidates
+        };
+
+        candidates.retain(|candidate| candidate.starts_with(word));
+        candidates.sort();
+        candidates.dedup();
+        (word_start, candidates)
+    }
+
+    /// Keys of the map found by following the `.a.b` accesses at the end of
+    /// `before` from the current value or a variable.
+    fn key_completions(&self, before: &str) -> Vec<String> {
+        let mut path = Vec::<&str>::new();
+        let mut rest = before;
+        loop {
+            let segment_start = rest
+                .rfind(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_'))
+                .map_or(0, |idx| idx + 1);
+            if segment_start == 0 || !rest[..segment_start].ends_with('.') {
+                break;
+            }
+            path.push(&rest[segment_start..]);
+            rest = &rest[..segment_start - 1];
+        }
+
+        // The path can start from a variable, as in `config.a.b`
+        let variable_start = rest
+            .rfind(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_'))
+            .map_or(0, |idx| idx + 1);
+        let mut val = match self.variables.get(&rest[variable_start..]) {
+            Some(variable) if !rest[..variable_start].ends_with('.') => variable.clone(),
+            _ => self.val.clone(),
+        };
+        for key in path.iter().rev() {
+            let next = match val.get_val() {
+                ValType::Map(map) => map.get(&Val::new_str(key)),
+                _ => None,
+            };
+            val = match next {
+                None => return Vec::new(),
+                Some(next) => next,
+            };
+        }
+
+        match val.get_val() {
+            ValType::Map(map) => map
+                .keys()
+                .iter()
+                .filter_map(|key| match key.get_val() {
+                    ValType::String(key)
+                        if key.starts_with(|ch: char| ch.is_ascii_alphabetic() || ch == '_')
+                            && key
+                                .chars()
+                                .all(|ch| ch.is_ascii_alphanumeric() || ch == '_') =>
+                    {
+                        Some(key.clone())
+                    }
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
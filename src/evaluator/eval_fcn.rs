@@ -1,24 +1,197 @@
-use super::EvalCtx;
+use super::{num_op, num_pow, seconds_to_delta, EvalCtx};
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
 use std::io::{Read, Write};
 
 use crate::ast_node::{AstNode, AstNodeType};
-use crate::val::{OrderedMap, Val, ValType};
+use crate::val::{datetime_to_iso, OrderedMap, Val, ValType, Zone, MAX_EXACT_FLOAT_INT};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use bigdecimal::num_bigint::Sign;
+use bigdecimal::{BigDecimal, RoundingMode, ToPrimitive};
+use chrono::format::StrftimeItems;
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, Utc};
+use chrono_tz::Tz;
+use regex::Regex;
+use std::fmt::Write as _;
 use std::process::{Command, Stdio};
+use std::str::FromStr;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Names of the built-in functions along with their usage, used for
+/// completion and `:help` in the REPL.
+pub const BUILTIN_FCNS: &[(&str, &str)] = &[
+    ("len", "len(): Number of elements in a list or map, or bytes in a string or bytes"),
+    ("map", "map(mapper): Transforms every element in a list using the mapper expression"),
+    ("group", "group(get_group_key): Groups elements in a list by the group key into {key, vals} maps"),
+    ("unique", "unique(): Removes duplicate values from a list, keeping the first of each"),
+    ("sort", "sort(get_sort_key): Sorts a list, optionally by a key expression"),
+    ("reverse", "reverse(): Reverses a list"),
+    ("filter", "filter(condition): Keeps the elements of a list where the condition is true"),
+    ("sum", "sum(): Adds up a list of numbers"),
+    ("min", "min(): Smallest element of a list"),
+    ("max", "max(): Largest element of a list"),
+    ("mean", "mean(get_key): Average of a list of numbers, optionally by a key expression"),
+    ("median", "median(get_key): Middle value of a list of numbers"),
+    ("percentile", "percentile(p, get_key): Value below which p percent of a list of numbers falls"),
+    ("quantiles", "quantiles(n, get_key): The n - 1 cut points dividing a list of numbers into n equal groups"),
+    ("variance", "variance(get_key): Population variance of a list of numbers, or the sample variance with :sample true"),
+    ("stddev", "stddev(get_key): Population standard deviation of a list of numbers, or the sample one with :sample true"),
+    ("mode", "mode(get_key): Most common value in a list"),
+    ("histogram", "histogram(get_key): Counts a list of numbers in :bins equal-width bins as {start, end, count} maps"),
+    ("count_by", "count_by(get_key): Map from each value, or key, in a list to how often it occurs"),
+    ("any", "any(condition): True if any element of a list of booleans (or condition) is true"),
+    ("all", "all(condition): True if every element of a list of booleans (or condition) is true"),
+    ("lines", "lines(): Splits a string or bytes into lines"),
+    ("join_lines", "join_lines(): Joins a list of strings with newlines"),
+    ("split", "split(pattern): Splits a string on the pattern, or on whitespace by default"),
+    ("join", "join(separator): Joins a list of strings with the separator"),
+    ("in", "in(): Reads stdin as bytes"),
+    ("inj", "inj(): Shorthand for in() | from_json()"),
+    ("str", "str(): Decodes bytes as a utf-8 string"),
+    ("bytes", "bytes(): Encodes a string as bytes"),
+    ("read", "read(): Reads the file at the input path as bytes"),
+    ("write", "write(file_path): Writes the input to the file"),
+    ("rj", "rj(): Shorthand for read() | from_json()"),
+    ("env", "env(): Map of the environment variables"),
+    ("from_json", "from_json(): Parses bytes or str as JSON"),
+    ("to_json", "to_json(): Converts a value to a JSON string"),
+    ("from_ndjson", "from_ndjson(): Parses newline-delimited JSON into a list"),
+    ("to_ndjson", "to_ndjson(): Converts a list to a string with one JSON value per line"),
+    ("from_toml", "from_toml(): Parses bytes or str as TOML"),
+    ("to_toml", "to_toml(): Converts a value to a TOML string"),
+    ("from_yaml", "from_yaml(): Parses bytes or str as YAML"),
+    ("to_yaml", "to_yaml(): Converts a value to a YAML string"),
+    ("from_num", "from_num(): Parses a string as a number"),
+    ("keys", "keys(): List of the keys of a map"),
+    ("values", "values(): List of the values of a map"),
+    ("items", "items(): Converts a map into a list of {key, val} maps"),
+    ("from_items", "from_items(): Converts a list of key,value pairs into a map"),
+    ("recursive_map", "recursive_map(get_children, mapper): Maps a tree bottom up, passing each node and its mapped children"),
+    ("recursive_flatten", "recursive_flatten(get_children): Flattens a tree into a list of its nodes"),
+    ("exec", "exec(cmd, args..., :cwd dir): Runs a command with the input as stdin and returns its stdout"),
+    ("range", "range(start, end, :step 1): List of numbers from start up to end"),
+    ("zip", "zip(): Combines a list of lists element by element"),
+    ("combinations", "combinations(): Every combination taking one element from each list in a list"),
+    ("repeat", "repeat(count): List with the input repeated count times"),
+    ("is_err", "is_err(): True if the input is an error"),
+    ("is_number", "is_number(): True if the input is a number"),
+    ("is_bool", "is_bool(): True if the input is a bool"),
+    ("is_string", "is_string(): True if the input is a string"),
+    ("is_list", "is_list(): True if the input is a list"),
+    ("is_map", "is_map(): True if the input is a map"),
+    ("is_bytes", "is_bytes(): True if the input is bytes"),
+    ("from_text_table", "from_text_table(): Parses whitespace-aligned command output into a list of maps"),
+    ("flatten", "flatten(): Flattens a list of lists into a list"),
+    (
+        "from_csv",
+        "from_csv(:header false, :delimiter \",\", :quote \"\\\"\", :infer_types false): Parses CSV into a list of lists, or a list of maps with :header true",
+    ),
+    ("to_csv", "to_csv(): Converts a list of lists or maps into a CSV string"),
+    ("to_tsv", "to_tsv(): Converts a list of lists or maps into a TSV string"),
+    (
+        "to_text_table",
+        "to_text_table(:border false, :max_width n): Renders a list of maps or lists as aligned columns with a header row",
+    ),
+    (
+        "to_markdown_table",
+        "to_markdown_table(): Renders a list of maps or lists as a Markdown table",
+    ),
+    ("to_html_table", "to_html_table(): Renders a list of maps or lists as an HTML table"),
+    ("catch", "catch(fallback): The fallback if the input is an error, otherwise the input"),
+    ("if", "if(cond, then, else): then if cond is true, otherwise else"),
+    ("has", "has(key): True if a map has the key or a list has the element"),
+    ("map_keys", "map_keys(mapper): Transforms every key in a map"),
+    ("map_values", "map_values(mapper): Transforms every value in a map"),
+    ("starts_with", "starts_with(prefix): True if a string starts with the prefix"),
+    ("ends_with", "ends_with(suffix): True if a string ends with the suffix"),
+    ("lower", "lower(): Converts a string to lowercase"),
+    ("upper", "upper(): Converts a string to uppercase"),
+    ("trim", "trim(): Removes whitespace from both ends of a string"),
+    ("abs", "abs(): Absolute value of a number"),
+    ("test", "test(re): True if a string matches a regex, :flags can contain i, m, s or x"),
+    (
+        "match",
+        "match(re): First regex match in a string as a map with its offset, length, groups and named captures, or null",
+    ),
+    ("scan", "scan(re): Every regex match in a string, or the groups of every match if the regex has groups"),
+    ("sub", "sub(re, replacement): Replaces the first regex match, $1 or $name refer to groups"),
+    ("gsub", "gsub(re, replacement): Replaces every regex match, $1 or $name refer to groups"),
+    ("split_re", "split_re(re): Splits a string on a regex"),
+    (
+        "to_datetime",
+        "to_datetime(): Parses ISO 8601, RFC 2822 or unix seconds as a datetime, :format can be a strftime format, \"unix\" or \"unix_ms\"",
+    ),
+    ("format_datetime", "format_datetime(format): Formats a datetime with a strftime format such as \"%Y-%m-%d\""),
+    ("to_timezone", "to_timezone(tz): Converts a datetime to a timezone such as \"Europe/Paris\", \"+05:30\" or \"local\""),
+    ("floor_time", "floor_time(duration): Rounds a datetime down to a multiple of a duration such as \"15m\" or \"1d\""),
+    ("now", "now(): The current time as a datetime in UTC"),
+    (
+        "parse_duration",
+        "parse_duration(): Parses a duration like \"1h30m\", \"250ms\" or \"P1DT2H\" as a number of seconds",
+    ),
+    (
+        "format_duration",
+        "format_duration(): Formats a number of seconds like \"1h30m\", or like \"PT1H30M\" with :style \"iso\"",
+    ),
+    ("parse_bytes", "parse_bytes(): Parses a size like \"1.5GiB\" or \"10 MB\" as a number of bytes"),
+    ("format_bytes", "format_bytes(): Formats a number of bytes like \"1.5GiB\", or like \"1.5GB\" with :si true"),
+    ("decimal", "decimal(): Converts a number or string to an exact decimal"),
+    (
+        "round",
+        "round(digits): Rounds a number to some decimal places, also given as :digits, and :mode can be half_up, half_even, half_down, up, down, ceiling or floor",
+    ),
+    ("floor", "floor(): Rounds a number down"),
+    ("ceil", "ceil(): Rounds a number up"),
+    ("sqrt", "sqrt(): Square root of a number"),
+    ("pow", "pow(exponent): Raises a number to a power, like **"),
+    ("log", "log(base): Logarithm of a number, the natural logarithm without a base"),
+    ("ln", "ln(): Natural logarithm of a number"),
+    ("log10", "log10(): Base 10 logarithm of a number"),
+    ("exp", "exp(): e raised to a number"),
+    ("sin", "sin(): Sine of an angle in radians"),
+    ("cos", "cos(): Cosine of an angle in radians"),
+    ("tan", "tan(): Tangent of an angle in radians"),
+    ("asin", "asin(): Arcsine of a number in radians"),
+    ("acos", "acos(): Arccosine of a number in radians"),
+    ("atan", "atan(): Arctangent of a number in radians"),
+    ("atan2", "atan2(x): Angle in radians of the point with the input as y and x"),
+    ("clamp", "clamp(lo, hi): Limits a number to a range"),
+    ("sign", "sign(): -1, 0 or 1 for negative numbers, zero and positive numbers"),
+    ("is_nan", "is_nan(): True if the input is NaN"),
+    ("is_finite", "is_finite(): True if the input is a number that isn't NaN or infinite"),
+    ("pi", "pi(): The constant π"),
+    ("e", "e(): The constant e"),
+    ("transpose", "transpose(): Swaps the rows and columns of a list of lists"),
+    ("matches_type", "matches_type(type): True if the input matches a type such as %{a: int}"),
+];
+
+/// Most decimal places that `round()` rounds to.
+const MAX_ROUND_DIGITS: i64 = 1000;
+
+/// Builtins that read stdin or files or have side effects, which are skipped
+/// when previewing results in the REPL.
+const IMPURE_FCNS: &[&str] = &["in", "inj", "read", "rj", "write", "exec"];
 
 impl EvalCtx {
     pub fn eval_fcn(&self, name: &str, args: &Vec<AstNode>) -> Val {
+        if IMPURE_FCNS.contains(&name) && self.abort_preview() {
+            return Val::new_err(format!("{}() is not run in previews", name).as_str());
+        }
+        // Builtins on long inputs, like sort(), can't check the deadline as they go
+        if self.preview_over_budget(super::val_len(&self.val)) {
+            return Val::new_err("preview value is too large");
+        }
+
         match name {
             "len" => {
                 if args.len() != 0 {
                     return Val::new_err("len() must be called with 0 arguments.");
                 }
                 match self.val.get_val() {
-                    ValType::List(val) => Val::new_f64(val.len() as f64),
-                    ValType::Map(val) => Val::new_f64(val.len() as f64),
-                    ValType::Bytes(val) => Val::new_f64(val.len() as f64),
-                    ValType::String(val) => Val::new_f64(val.as_bytes().len() as f64),
+                    ValType::List(val) => Val::new_int(val.len() as i64),
+                    ValType::Map(val) => Val::new_int(val.len() as i64),
+                    ValType::Bytes(val) => Val::new_int(val.len() as i64),
+                    ValType::String(val) => Val::new_int(val.as_bytes().len() as i64),
                     _ => {
                         Val::new_err("len() can only be called on a list or map or bytes or string")
                     }
@@ -53,11 +226,7 @@ impl EvalCtx {
                         let mut key_to_group_idx = HashMap::<Val, usize>::new();
 
                         for elem in val {
-                            let ctx = EvalCtx {
-                                variables: self.variables.clone(),
-                                val: elem.clone(),
-                            };
-                            let key_val = ctx.eval(&args[0]).val;
+                            let key_val = self.with_val(elem.clone()).eval(&args[0]).val;
                             match key_to_group_idx.entry(key_val.clone()) {
                                 Entry::Occupied(entry) => {
                                     groups[*entry.get()].1.push(elem.clone());
@@ -112,11 +281,7 @@ impl EvalCtx {
                             }
                             Some(sort_expr) => {
                                 values.sort_by_cached_key(|elem| {
-                                    let ctx = EvalCtx {
-                                        variables: self.variables.clone(),
-                                        val: elem.clone(),
-                                    };
-                                    ctx.eval(&sort_expr).val
+                                    self.with_val(elem.clone()).eval(sort_expr).val
                                 });
                             }
                         }
@@ -162,32 +327,38 @@ impl EvalCtx {
             }
             "sum" => match self.val.get_val() {
                 ValType::List(list) => {
-                    let mut total: f64 = 0.0;
+                    // The total stays an integer until a float is added or it overflows
+                    let mut total = Val::new_int(0);
                     for elem in list {
-                        match elem.get_val() {
-                            ValType::Float64(val) => {
-                                total += val;
-                            }
-                            _ => {
+                        total = match num_op(
+                            &total,
+                            elem,
+                            self.decimal,
+                            i64::checked_add,
+                            |left, right| Some(left + right),
+                            |left, right| left + right,
+                        ) {
+                            Some(total) => total,
+                            None => {
                                 return Val::new_err(
                                     "sum() can only be called on a list of numbers",
                                 )
                             }
-                        }
+                        };
                     }
-                    Val::new_f64(total)
+                    total
                 }
                 _ => Val::new_err("sum() has to be called on a list"),
             },
             "min" => match self.val.get_val() {
                 ValType::List(list) => {
-                    let mut has_val = false;
-                    let mut total: f64 = f64::MAX;
+                    let mut result: Option<&Val> = None;
                     for elem in list {
                         match elem.get_val() {
-                            ValType::Float64(val) => {
-                                total = total.min(*val);
-                                has_val = true;
+                            ValType::Int(_) | ValType::Float64(_) | ValType::Decimal(_) => {
+                                if result.is_none_or(|result| elem < result) {
+                                    result = Some(elem);
+                                }
                             }
                             _ => {
                                 return Val::new_err(
@@ -197,23 +368,22 @@ impl EvalCtx {
                         }
                     }
 
-                    if has_val {
-                        Val::new_f64(total)
-                    } else {
-                        Val::new_null()
+                    match result {
+                        Some(result) => result.clone(),
+                        None => Val::new_null(),
                     }
                 }
                 _ => Val::new_err("min() has to be called on a list"),
             },
             "max" => match self.val.get_val() {
                 ValType::List(list) => {
-                    let mut has_val = false;
-                    let mut total: f64 = f64::MIN;
+                    let mut result: Option<&Val> = None;
                     for elem in list {
                         match elem.get_val() {
-                            ValType::Float64(val) => {
-                                total = total.max(*val);
-                                has_val = true;
+                            ValType::Int(_) | ValType::Float64(_) | ValType::Decimal(_) => {
+                                if result.is_none_or(|result| elem > result) {
+                                    result = Some(elem);
+                                }
                             }
                             _ => {
                                 return Val::new_err(
@@ -223,14 +393,199 @@ impl EvalCtx {
                         }
                     }
 
-                    if has_val {
-                        Val::new_f64(total)
-                    } else {
-                        Val::new_null()
+                    match result {
+                        Some(result) => result.clone(),
+                        None => Val::new_null(),
                     }
                 }
                 _ => Val::new_err("max() has to be called on a list"),
             },
+            "mean" | "median" | "percentile" | "quantiles" | "variance" | "stddev" | "mode"
+            | "histogram" | "count_by" => {
+                let mut positional = Vec::<&AstNode>::new();
+                let mut bins: usize = 10;
+                let mut sample = false;
+                for arg in args {
+                    let (keyword, stmt) = match keyword_arg(arg, name) {
+                        Ok(Some(keyword_arg)) => keyword_arg,
+                        Ok(None) => {
+                            positional.push(arg);
+                            continue;
+                        }
+                        Err(err) => return err,
+                    };
+                    let val = self.eval(stmt).val;
+                    match (name, keyword, val.get_val()) {
+                        ("histogram", "bins", ValType::Int(val))
+                            if *val > 0 && *val <= MAX_STAT_GROUPS =>
+                        {
+                            bins = *val as usize
+                        }
+                        ("histogram", "bins", _) => {
+                            return Val::new_err(
+                                format!(
+                                    ":bins keyword argument in histogram() must be an integer from 1 to {}",
+                                    MAX_STAT_GROUPS
+                                )
+                                .as_str(),
+                            )
+                        }
+                        ("variance" | "stddev", "sample", ValType::Bool(val)) => sample = *val,
+                        ("variance" | "stddev", "sample", _) => {
+                            return Val::new_err(
+                                format!(":sample keyword argument in {}() must be a boolean", name)
+                                    .as_str(),
+                            )
+                        }
+                        _ => {
+                            return Val::new_err(
+                                format!("Invalid keyword argument in {}()", name).as_str(),
+                            )
+                        }
+                    }
+                }
+
+                let num_params = if name == "percentile" || name == "quantiles" {
+                    1
+                } else {
+                    0
+                };
+                if positional.len() < num_params || positional.len() > num_params + 1 {
+                    return Val::new_err(
+                        format!(
+                            "{}() must be called with {} or {} arguments",
+                            name,
+                            num_params,
+                            num_params + 1
+                        )
+                        .as_str(),
+                    );
+                }
+                let values = match self.eval_stat_values(name, positional.get(num_params).copied())
+                {
+                    Ok(values) => values,
+                    Err(err) => return err,
+                };
+
+                match name {
+                    "mode" => {
+                        let mut counts = OrderedMap::new();
+                        let mut result: Option<(&Val, i64)> = None;
+                        for val in &values {
+                            let count = match counts.get(val) {
+                                Some(count) => count.as_f64().unwrap() as i64 + 1,
+                                None => 1,
+                            };
+                            counts.insert(val, &Val::new_int(count));
+                            if result.is_none_or(|(_, max_count)| count > max_count) {
+                                result = Some((val, count));
+                            }
+                        }
+                        match result {
+                            Some((val, _)) => val.clone(),
+                            None => Val::new_null(),
+                        }
+                    }
+                    "count_by" => {
+                        let mut counts = OrderedMap::new();
+                        for val in &values {
+                            let count = match counts.get(val) {
+                                Some(count) => count.as_f64().unwrap() as i64 + 1,
+                                None => 1,
+                            };
+                            counts.insert(val, &Val::new_int(count));
+                        }
+                        Val::new_map(counts)
+                    }
+                    _ if values.iter().any(|val| val.as_f64().is_none()) => Val::new_err(
+                        format!("{}() can only be called on a list of numbers", name).as_str(),
+                    ),
+                    "histogram" => histogram(&values, bins),
+                    _ if values.is_empty() => Val::new_null(),
+                    "mean" => {
+                        let mut total = Val::new_int(0);
+                        for val in &values {
+                            total = num_op(
+                                &total,
+                                val,
+                                self.decimal,
+                                i64::checked_add,
+                                |left, right| Some(left + right),
+                                |left, right| left + right,
+                            )
+                            .unwrap();
+                        }
+                        self.exact_div(&total, values.len() as i64)
+                    }
+                    "variance" | "stddev" => {
+                        // Welford's algorithm avoids the cancellation of summing squares
+                        let mut mean = 0.0;
+                        let mut squares = 0.0;
+                        for (idx, val) in values.iter().enumerate() {
+                            let val = val.as_f64().unwrap();
+                            let delta = val - mean;
+                            mean += delta / (idx + 1) as f64;
+                            squares += delta * (val - mean);
+                        }
+                        let divisor = if sample {
+                            values.len() - 1
+                        } else {
+                            values.len()
+                        };
+                        if divisor == 0 {
+                            return Val::new_null();
+                        }
+                        let variance = squares / divisor as f64;
+                        if name == "stddev" {
+                            Val::new_f64(variance.sqrt())
+                        } else {
+                            Val::new_f64(variance)
+                        }
+                    }
+                    _ => {
+                        let mut sorted = values;
+                        sorted.sort();
+                        match name {
+                            "median" if sorted.len() % 2 == 0 => {
+                                let upper = sorted.len() / 2;
+                                let total = num_op(
+                                    &sorted[upper - 1],
+                                    &sorted[upper],
+                                    self.decimal,
+                                    i64::checked_add,
+                                    |left, right| Some(left + right),
+                                    |left, right| left + right,
+                                )
+                                .unwrap();
+                                self.exact_div(&total, 2)
+                            }
+                            "median" => sorted[sorted.len() / 2].clone(),
+                            "percentile" => match self.eval(positional[0]).val.as_f64() {
+                                Some(p) if (0.0..=100.0).contains(&p) => {
+                                    interpolate_sorted(&sorted, p / 100.0)
+                                }
+                                _ => Val::new_err("percentile() p must be a number from 0 to 100"),
+                            },
+                            _ => match self.eval_i64(positional[0]) {
+                                Some(n) if (2..=MAX_STAT_GROUPS).contains(&n) => Val::new_list(
+                                    (1..n)
+                                        .map(|idx| {
+                                            interpolate_sorted(&sorted, idx as f64 / n as f64)
+                                        })
+                                        .collect::<Vec<_>>(),
+                                ),
+                                _ => Val::new_err(
+                                    format!(
+                                        "quantiles() n must be an integer from 2 to {}",
+                                        MAX_STAT_GROUPS
+                                    )
+                                    .as_str(),
+                                ),
+                            },
+                        }
+                    }
+                }
+            }
             "any" => {
                 if args.len() != 0 && args.len() != 1 {
                     return Val::new_err("any() must be called with 0 or 1 arguments");
@@ -364,6 +719,69 @@ impl EvalCtx {
                 }
                 _ => Val::new_err("split() must be called on a string"),
             },
+            "test" | "match" | "scan" | "sub" | "gsub" | "split_re" => {
+                let text = match self.val.get_val() {
+                    ValType::String(text) => text,
+                    ValType::Bytes(_) => {
+                        let text = self.eval_fcn("str", args);
+                        return self.with_val(text).eval_fcn(name, args);
+                    }
+                    _ => {
+                        return Val::new_err(
+                            format!("{}() must be called on a string", name).as_str(),
+                        )
+                    }
+                };
+                let (regex, other_args) = match self.eval_regex_args(name, args) {
+                    Ok(result) => result,
+                    Err(err) => return err,
+                };
+                let num_args = if name == "sub" || name == "gsub" {
+                    1
+                } else {
+                    0
+                };
+                if other_args.len() != num_args {
+                    return Val::new_err(
+                        format!("{}() must be called with {} arguments", name, num_args + 1)
+                            .as_str(),
+                    );
+                }
+
+                match name {
+                    "test" => Val::new_bool(regex.is_match(text)),
+                    "match" => match regex.captures(text) {
+                        Some(captures) => regex_match_to_val(&regex, text, &captures),
+                        None => Val::new_null(),
+                    },
+                    "scan" => Val::new_list(
+                        regex
+                            .captures_iter(text)
+                            .map(|captures| {
+                                if captures.len() == 1 {
+                                    Val::new_str(&captures[0])
+                                } else {
+                                    Val::new_list(regex_groups(&captures))
+                                }
+                            })
+                            .collect::<Vec<_>>(),
+                    ),
+                    "sub" | "gsub" => {
+                        let replacement = self.eval(other_args[0]).val;
+                        let replacement = match replacement.get_val() {
+                            ValType::String(replacement) => replacement.as_str(),
+                            _ => {
+                                return Val::new_err(
+                                    format!("{}() replacement must be a string", name).as_str(),
+                                )
+                            }
+                        };
+                        let limit = if name == "sub" { 1 } else { 0 };
+                        Val::new_str(regex.replacen(text, limit, replacement).as_ref())
+                    }
+                    _ => Val::new_list(regex.split(text).map(Val::new_str).collect::<Vec<_>>()),
+                }
+            }
             "join" => match self.val.get_val() {
                 ValType::List(elems) => {
                     let joiner = self.eval(&args[0]).val;
@@ -378,7 +796,7 @@ impl EvalCtx {
                             ValType::String(elem_str) => {
                                 strings_to_join.push(elem_str.clone());
                             }
-                            ValType::Float64(_) => {
+                            ValType::Int(_) | ValType::Float64(_) | ValType::Decimal(_) => {
                                 let mut buf = Vec::<u8>::new();
                                 match elem.write_to_str(&mut buf, 0, false) {
                                     Err(_) => return Val::new_err("Unable to serialize element"),
@@ -479,17 +897,39 @@ impl EvalCtx {
                 Val::new_map(OrderedMap::from_kv_pair_slice(kv_pairs.as_slice()))
             }
             "from_json" => match self.val.get_val() {
-                ValType::String(val) => Val::from_json_str(val.as_str()),
+                ValType::String(val) => Val::from_json_str(val.as_str(), self.decimal),
                 ValType::Bytes(_) => {
                     let text = self.eval_fcn("str", args);
                     self.with_val(text).eval_fcn(name, args)
                 }
                 _ => Val::new_err("from_json() must be called on a string"),
             },
-            "to_json" => match serde_json::to_string(&self.val) {
+            "to_json" => match self.val.serialize_exact(serde_json::to_string) {
                 Ok(string) => Val::new_str(string.as_str()),
                 Err(_) => Val::new_err("Unable to serialize json"),
             },
+            "from_ndjson" => match self.val.get_val() {
+                ValType::String(val) => Val::from_ndjson_str(val.as_str(), self.decimal),
+                ValType::Bytes(_) => {
+                    let text = self.eval_fcn("str", args);
+                    self.with_val(text).eval_fcn(name, args)
+                }
+                _ => Val::new_err("from_ndjson() must be called on a string"),
+            },
+            "to_ndjson" => match self.val.get_val() {
+                ValType::List(vals) => {
+                    let mut buffer = String::new();
+                    for val in vals {
+                        match val.serialize_exact(serde_json::to_string) {
+                            Err(_) => return Val::new_err("Unable to serialize json"),
+                            Ok(line) => buffer.push_str(line.as_str()),
+                        }
+                        buffer.push('\n');
+                    }
+                    Val::new_str(buffer.as_str())
+                }
+                _ => Val::new_err("to_ndjson() must be called on a list"),
+            },
             "from_toml" => match self.val.get_val() {
                 ValType::String(val) => Val::from_toml_str(val.as_str()),
                 ValType::Bytes(_) => {
@@ -498,7 +938,7 @@ impl EvalCtx {
                 }
                 _ => Val::new_err("from_toml() must be called on a string"),
             },
-            "to_toml" => match toml::to_string(&self.val) {
+            "to_toml" => match self.val.serialize_exact(toml::to_string) {
                 Ok(string) => Val::new_str(string.as_str()),
                 Err(_) => Val::new_err("Unable to serialize toml"),
             },
@@ -510,15 +950,15 @@ impl EvalCtx {
                 }
                 _ => Val::new_err("from_yaml() must be called on a string"),
             },
-            "to_yaml" => match serde_yaml::to_string(&self.val) {
+            "to_yaml" => match self.val.serialize_exact(serde_yaml::to_string) {
                 Ok(string) => Val::new_str(string.as_str()),
                 Err(_) => Val::new_err("Unable to serialize yaml"),
             },
             "from_num" => match self.val.get_val() {
-                ValType::String(val) => Val::new_f64(match val.parse::<f64>() {
-                    Err(_) => return Val::new_err("unable to parse number"),
-                    Ok(val) => val,
-                }),
+                ValType::String(val) => match Val::parse_number(val, self.decimal) {
+                    Some(val) => val,
+                    None => Val::new_err("unable to parse number"),
+                },
                 _ => Val::new_err("from_num() must be called on a string"),
             },
             "keys" => match self.val.get_val() {
@@ -662,29 +1102,24 @@ impl EvalCtx {
                 let mut arg_strs = Vec::<String>::new();
                 let mut cwd: Option<String> = None;
                 for arg in args {
-                    match arg.get_type() {
-                        AstNodeType::KeywordArgument(keyword, val) => {
-                            let keyword = match keyword.get_type() {
-                                AstNodeType::Identifier(keyword) => keyword,
-                                _ => panic!(),
-                            };
-                            match keyword.as_str() {
-                                "cwd" => {
-                                    let cwd_val = self.eval(val).val;
-                                    let cwd_val = match cwd_val.get_val() {
-                                        ValType::String(cwd_val) => cwd_val,
-                                        _ => {
-                                            return Val::new_err(
-                                                "In exec(), the :env keyword must be a string",
-                                            );
-                                        }
-                                    };
-                                    cwd = Some(cwd_val.clone());
-                                }
-                                _ => return Val::new_err("Unknown keyword passed to exec()"),
+                    match keyword_arg(arg, name) {
+                        Err(err) => return err,
+                        Ok(Some((keyword, val))) => match keyword {
+                            "cwd" => {
+                                let cwd_val = self.eval(val).val;
+                                let cwd_val = match cwd_val.get_val() {
+                                    ValType::String(cwd_val) => cwd_val,
+                                    _ => {
+                                        return Val::new_err(
+                                            "In exec(), the :env keyword must be a string",
+                                        );
+                                    }
+                                };
+                                cwd = Some(cwd_val.clone());
                             }
-                        }
-                        _ => {
+                            _ => return Val::new_err("Unknown keyword passed to exec()"),
+                        },
+                        Ok(None) => {
                             let arg_val = self.eval(arg).val;
                             let arg_str = match arg_val.get_val() {
                                 ValType::String(str) => str,
@@ -746,12 +1181,9 @@ impl EvalCtx {
                 let mut first_idx: Option<i64> = None;
                 let mut second_idx: Option<i64> = None;
                 for arg in args {
-                    match arg.get_type() {
-                        AstNodeType::KeywordArgument(keyword, stmt) => {
-                            let keyword = match keyword.get_type() {
-                                AstNodeType::Identifier(keyword) => keyword,
-                                _ => panic!(),
-                            };
+                    match keyword_arg(arg, name) {
+                        Err(err) => return err,
+                        Ok(Some((keyword, stmt))) => {
                             if keyword == "step" {
                                 step =
                                     match self.eval_i64(stmt) {
@@ -764,7 +1196,7 @@ impl EvalCtx {
                                 return Val::new_err("Invalid keyword argument in range()");
                             }
                         }
-                        _ => {
+                        Ok(None) => {
                             match first_idx {
                                 None => {
                                     first_idx = match self.eval_i64(arg) {
@@ -819,18 +1251,23 @@ impl EvalCtx {
                 if step == 0 {
                     return Val::new_err(":step keyword argument in range() cannot be zero.");
                 }
+                let len = (end as i128 - start as i128 + step as i128 - step.signum() as i128)
+                    / step as i128;
+                if self.preview_over_budget(len.clamp(0, usize::MAX as i128) as usize) {
+                    return Val::new_err("preview value is too large");
+                }
 
                 let mut result = Vec::<Val>::new();
                 if step > 0 {
                     let mut idx = start;
                     while idx < end {
-                        result.push(Val::new_f64(idx as f64));
+                        result.push(Val::new_int(idx));
                         idx += step;
                     }
                 } else {
                     let mut idx = start;
                     while idx > end {
-                        result.push(Val::new_f64(idx as f64));
+                        result.push(Val::new_int(idx));
                         idx += step;
                     }
                 }
@@ -887,6 +1324,13 @@ impl EvalCtx {
                     }
                 }
 
+                let len = arg_lists
+                    .iter()
+                    .fold(1usize, |len, list| len.saturating_mul(list.len()));
+                if self.preview_over_budget(len) {
+                    return Val::new_err("preview value is too large");
+                }
+
                 let mut indexes = vec![0 as usize; arg_lists.len()];
 
                 let mut results = Vec::<Val>::new();
@@ -938,14 +1382,16 @@ impl EvalCtx {
 
                 let arg_val = self.eval(&args[0]).val;
                 match arg_val.get_val() {
-                    ValType::Float64(val) => {
-                        let val = *val;
-                        if val == val.trunc() && val >= 0.0 {
-                            let val = val as usize;
-                            let result = (0..val).map(|_| self.val.clone()).collect::<Vec<_>>();
-                            Val::new_list(result)
-                        } else {
-                            Val::new_err("repeat() must be called with a positive integer")
+                    ValType::Int(_) | ValType::Float64(_) | ValType::Decimal(_) => {
+                        match self.eval_i64(&args[0]) {
+                            Some(val) if val >= 0 => {
+                                if self.preview_over_budget(val as usize) {
+                                    return Val::new_err("preview value is too large");
+                                }
+                                let result = (0..val).map(|_| self.val.clone()).collect::<Vec<_>>();
+                                Val::new_list(result)
+                            }
+                            _ => Val::new_err("repeat() must be called with a positive integer"),
                         }
                     }
                     _ => Val::new_err("repeat() must be called with a number"),
@@ -956,7 +1402,7 @@ impl EvalCtx {
                 _ => Val::new_bool(false),
             },
             "is_number" => match self.val.get_val() {
-                ValType::Float64(_) => Val::new_bool(true),
+                ValType::Int(_) | ValType::Float64(_) | ValType::Decimal(_) => Val::new_bool(true),
                 _ => Val::new_bool(false),
             },
             "is_bool" => match self.val.get_val() {
@@ -1068,43 +1514,129 @@ impl EvalCtx {
                 _ => Val::new_err("flatten() must be called on a list"),
             },
             "from_csv" => {
+                let mut header = false;
+                let mut infer_types = false;
+                let mut builder = csv::ReaderBuilder::new();
+                builder.has_headers(false);
+                for arg in args {
+                    let (keyword, stmt) = match keyword_arg(arg, name) {
+                        Ok(Some(keyword_arg)) => keyword_arg,
+                        Ok(None) => return Val::new_err("from_csv() only takes keyword arguments"),
+                        Err(err) => return err,
+                    };
+                    let val = self.eval(stmt).val;
+                    match (keyword, val.get_val()) {
+                        ("header", ValType::Bool(val)) => header = *val,
+                        ("infer_types", ValType::Bool(val)) => infer_types = *val,
+                        ("delimiter", ValType::String(val)) if val.len() == 1 => {
+                            builder.delimiter(val.as_bytes()[0]);
+                        }
+                        ("quote", ValType::String(val)) if val.len() == 1 => {
+                            builder.quote(val.as_bytes()[0]);
+                        }
+                        ("header" | "infer_types", _) => {
+                            return Val::new_err(
+                                format!(
+                                    ":{} keyword argument in from_csv() must be a boolean",
+                                    keyword
+                                )
+                                .as_str(),
+                            )
+                        }
+                        ("delimiter" | "quote", _) => {
+                            return Val::new_err(
+                                format!(
+                                    ":{} keyword argument in from_csv() must be a single character",
+                                    keyword
+                                )
+                                .as_str(),
+                            )
+                        }
+                        _ => return Val::new_err("Invalid keyword argument in from_csv()"),
+                    }
+                }
+
                 let mut reader = match self.val.get_val() {
-                    ValType::Bytes(bytes) => csv::Reader::from_reader(bytes.as_slice()),
-                    ValType::String(val) => csv::Reader::from_reader(val.as_bytes()),
+                    ValType::Bytes(bytes) => builder.from_reader(bytes.as_slice()),
+                    ValType::String(val) => builder.from_reader(val.as_bytes()),
                     _ => return Val::new_err("from_csv() must be called on string or bytes"),
                 };
 
                 let mut lines = Vec::<Val>::new();
-
-                if reader.has_headers() {
-                    match reader.headers() {
+                let mut columns: Option<Vec<Val>> = None;
+                for record in reader.records() {
+                    let record = match record {
                         Err(_) => return Val::new_err("from_csv() is unable to read csv"),
-                        Ok(record) => {
-                            let mut line = Vec::<Val>::new();
-                            for elem in record.iter() {
-                                line.push(Val::new_str(elem));
-                            }
-                            lines.push(Val::new_list(line));
-                        }
+                        Ok(record) => record,
+                    };
+                    if header && columns.is_none() {
+                        columns = Some(record.iter().map(Val::new_str).collect());
+                        continue;
                     }
-                }
 
-                for record in reader.records() {
-                    let mut line = Vec::<Val>::new();
-                    match record {
-                        Err(_) => return Val::new_err("from_csv() is unable to read csv"),
-                        Ok(record) => {
-                            for elem in record.iter() {
-                                line.push(Val::new_str(elem));
+                    let fields = record.iter().map(|field| {
+                        if infer_types {
+                            infer_csv_field(field, self.decimal)
+                        } else {
+                            Val::new_str(field)
+                        }
+                    });
+                    match &columns {
+                        None => lines.push(Val::new_list(fields.collect())),
+                        Some(columns) => {
+                            let mut line = OrderedMap::new();
+                            for (column, field) in columns.iter().zip(fields) {
+                                line.insert(column, &field);
                             }
+                            lines.push(Val::new_map(line));
                         }
                     }
-                    lines.push(Val::new_list(line))
                 }
 
                 Val::new_list(lines)
             }
             "to_csv" => write_csv_to_val(&self.val, b',', "to_csv"),
+            "to_text_table" => {
+                let mut opts = TextTableOptions {
+                    border: false,
+                    max_width: None,
+                };
+                for arg in args {
+                    let (keyword, stmt) = match keyword_arg(arg, name) {
+                        Ok(Some(keyword_arg)) => keyword_arg,
+                        Ok(None) => {
+                            return Val::new_err(
+                                "to_text_table() only takes the :border and :max_width keyword arguments",
+                            )
+                        }
+                        Err(err) => return err,
+                    };
+                    match keyword {
+                        "border" => {
+                            opts.border = match self.eval(stmt).val.get_val() {
+                                ValType::Bool(border) => *border,
+                                _ => {
+                                    return Val::new_err(
+                                        ":border keyword argument in to_text_table() must be a boolean",
+                                    )
+                                }
+                            }
+                        }
+                        "max_width" => {
+                            opts.max_width = match self.eval_i64(stmt) {
+                                Some(max_width) if max_width > 0 => Some(max_width as usize),
+                                _ => return Val::new_err(
+                                    ":max_width keyword argument in to_text_table() must be a positive integer",
+                                ),
+                            }
+                        }
+                        _ => return Val::new_err("Invalid keyword argument in to_text_table()"),
+                    }
+                }
+                write_text_table_to_val(&self.val, &opts)
+            }
+            "to_markdown_table" => write_markdown_table_to_val(&self.val),
+            "to_html_table" => write_html_table_to_val(&self.val),
             "to_tsv" => write_csv_to_val(&self.val, b'\t', "to_tsv"),
             "catch" => {
                 if args.len() != 1 {
@@ -1231,9 +1763,471 @@ impl EvalCtx {
                 _ => Val::new_err("trim() must be called on a string"),
             },
             "abs" => match self.val.get_val() {
+                ValType::Int(val) => match val.checked_abs() {
+                    Some(abs) => Val::new_int(abs),
+                    None => Val::new_f64((*val as f64).abs()),
+                },
                 ValType::Float64(val) => Val::new_f64(val.abs()),
+                ValType::Decimal(val) => Val::new_decimal(val.abs()),
                 _ => Val::new_err("abs() must be called on a number"),
             },
+            "to_datetime" => {
+                let mut format: Option<String> = None;
+                for arg in args {
+                    let (keyword, stmt) = match keyword_arg(arg, name) {
+                        Ok(Some(keyword_arg)) => keyword_arg,
+                        Ok(None) => {
+                            return Val::new_err("to_datetime() only takes keyword arguments")
+                        }
+                        Err(err) => return err,
+                    };
+                    let val = self.eval(stmt).val;
+                    match (keyword, val.get_val()) {
+                        ("format", ValType::String(val)) => format = Some(val.clone()),
+                        ("format", _) => {
+                            return Val::new_err(
+                                ":format keyword argument in to_datetime() must be a string",
+                            )
+                        }
+                        _ => return Val::new_err("Invalid keyword argument in to_datetime()"),
+                    }
+                }
+
+                let result = match (self.val.get_val(), format.as_deref()) {
+                    (ValType::Datetime(..), None) => return self.val.clone(),
+                    (ValType::String(text), Some(format @ ("unix" | "unix_ms"))) => {
+                        match Val::parse_number(text.trim(), false) {
+                            Some(num) => unix_to_datetime(&num, format == "unix_ms"),
+                            None => None,
+                        }
+                    }
+                    (ValType::String(text), None) => parse_datetime(text),
+                    (ValType::String(text), Some(format)) => {
+                        parse_datetime_with_format(text, format)
+                    }
+                    (
+                        ValType::Int(_) | ValType::Float64(_) | ValType::Decimal(_),
+                        None | Some("unix"),
+                    ) => unix_to_datetime(&self.val, false),
+                    (
+                        ValType::Int(_) | ValType::Float64(_) | ValType::Decimal(_),
+                        Some("unix_ms"),
+                    ) => unix_to_datetime(&self.val, true),
+                    (ValType::Int(_) | ValType::Float64(_) | ValType::Decimal(_), Some(_)) => {
+                        return Val::new_err(
+                            "to_datetime() :format must be \"unix\" or \"unix_ms\" for numbers",
+                        )
+                    }
+                    _ => return Val::new_err("to_datetime() must be called on a string or number"),
+                };
+                match result {
+                    Some(datetime) => Val::new_datetime(datetime),
+                    None => {
+                        let mut buf = Vec::<u8>::new();
+                        self.val.write_to_str(&mut buf, 0, false).unwrap();
+                        Val::new_err(
+                            format!(
+                                "to_datetime() unable to parse {} as a datetime",
+                                String::from_utf8_lossy(&buf)
+                            )
+                            .as_str(),
+                        )
+                    }
+                }
+            }
+            "format_datetime" => {
+                let datetime = match self.val.get_val() {
+                    ValType::Datetime(datetime, _) => datetime,
+                    _ => return Val::new_err("format_datetime() must be called on a datetime"),
+                };
+                if args.len() != 1 {
+                    return Val::new_err("format_datetime() must be called with 1 argument");
+                }
+                let format = self.eval(&args[0]).val;
+                let format = match format.get_val() {
+                    ValType::String(format) => format,
+                    _ => return Val::new_err("format_datetime() format must be a string"),
+                };
+                let items = match StrftimeItems::new(format).parse() {
+                    Ok(items) => items,
+                    Err(_) => {
+                        return Val::new_err(
+                            format!("format_datetime() has an invalid format \"{}\"", format)
+                                .as_str(),
+                        )
+                    }
+                };
+                let mut result = String::new();
+                match write!(result, "{}", datetime.format_with_items(items.iter())) {
+                    Ok(_) => Val::new_str(result.as_str()),
+                    Err(_) => Val::new_err(
+                        format!("format_datetime() has an invalid format \"{}\"", format).as_str(),
+                    ),
+                }
+            }
+            "to_timezone" => {
+                let datetime = match self.val.get_val() {
+                    ValType::Datetime(datetime, _) => datetime,
+                    _ => return Val::new_err("to_timezone() must be called on a datetime"),
+                };
+                if args.len() != 1 {
+                    return Val::new_err("to_timezone() must be called with 1 argument");
+                }
+                let tz = self.eval(&args[0]).val;
+                let tz = match tz.get_val() {
+                    ValType::String(tz) => tz.as_str(),
+                    _ => return Val::new_err("to_timezone() timezone must be a string"),
+                };
+                if tz == "local" {
+                    return Val::new_zoned_datetime(*datetime, Some(Zone::Local));
+                }
+                if let Ok(tz) = tz.parse::<Tz>() {
+                    return Val::new_zoned_datetime(*datetime, Some(Zone::Named(tz)));
+                }
+                match tz.parse::<FixedOffset>() {
+                    Ok(offset) => Val::new_datetime(datetime.with_timezone(&offset)),
+                    Err(_) => {
+                        Val::new_err(format!("to_timezone() unknown timezone \"{}\"", tz).as_str())
+                    }
+                }
+            }
+            "floor_time" => {
+                let (datetime, zone) = match self.val.get_val() {
+                    ValType::Datetime(datetime, zone) => (datetime, *zone),
+                    _ => return Val::new_err("floor_time() must be called on a datetime"),
+                };
+                if args.len() != 1 {
+                    return Val::new_err("floor_time() must be called with 1 argument");
+                }
+                let duration = self.eval(&args[0]).val;
+                let nanos = match duration.get_val() {
+                    ValType::String(duration) => parse_duration_nanos(duration),
+                    _ => seconds_to_delta(&duration).and_then(|delta| delta.num_nanoseconds()),
+                };
+                let nanos = match nanos {
+                    Some(nanos) if nanos > 0 => nanos as i128,
+                    _ => {
+                        return Val::new_err(
+                            "floor_time() duration must be positive, such as \"1h\" or 3600",
+                        )
+                    }
+                };
+                // Floor in local time so that "1d" rounds down to local midnight
+                let offset = datetime.offset().local_minus_utc() as i128 * 1_000_000_000;
+                let local = match datetime.timestamp_nanos_opt() {
+                    Some(timestamp) => timestamp as i128 + offset,
+                    None => return Val::new_err("datetime out of range"),
+                };
+                let floored = local - local.rem_euclid(nanos) - offset;
+                let floored = match i64::try_from(floored) {
+                    Ok(floored) => {
+                        DateTime::from_timestamp_nanos(floored).with_timezone(datetime.offset())
+                    }
+                    Err(_) => return Val::new_err("datetime out of range"),
+                };
+                // The offset may differ at the floored time, such as midnight
+                // before clocks went forward, so look the local time up again
+                let floored = zone
+                    .and_then(|zone| zone.at_local(&floored.naive_local()))
+                    .unwrap_or(floored);
+                Val::new_zoned_datetime(floored, zone)
+            }
+            "now" => Val::new_datetime(Utc::now().fixed_offset()),
+            "parse_duration" => match self.val.get_val() {
+                ValType::String(text) => match parse_duration_nanos(text) {
+                    Some(nanos) if nanos as i128 % NANOS_PER_SEC == 0 => {
+                        Val::new_int(nanos / NANOS_PER_SEC as i64)
+                    }
+                    Some(nanos) => Val::new_f64(nanos as f64 / 1e9),
+                    None => Val::new_err(
+                        format!(
+                            "parse_duration() unable to parse \"{}\" as a duration",
+                            text
+                        )
+                        .as_str(),
+                    ),
+                },
+                ValType::Int(_) | ValType::Float64(_) | ValType::Decimal(_) => self.val.clone(),
+                _ => Val::new_err("parse_duration() must be called on a string"),
+            },
+            "format_duration" => {
+                let mut iso = false;
+                for arg in args {
+                    let (keyword, stmt) = match keyword_arg(arg, name) {
+                        Ok(Some(keyword_arg)) => keyword_arg,
+                        Ok(None) => {
+                            return Val::new_err("format_duration() only takes keyword arguments")
+                        }
+                        Err(err) => return err,
+                    };
+                    if keyword != "style" {
+                        return Val::new_err("Invalid keyword argument in format_duration()");
+                    }
+                    iso = match self.eval(stmt).val.get_val() {
+                        ValType::String(style) if style == "short" => false,
+                        ValType::String(style) if style == "iso" => true,
+                        _ => {
+                            return Val::new_err(
+                                ":style keyword argument in format_duration() must be \"short\" or \"iso\"",
+                            )
+                        }
+                    };
+                }
+                let nanos = match self.val.as_f64() {
+                    Some(_) => {
+                        seconds_to_delta(&self.val).and_then(|delta| delta.num_nanoseconds())
+                    }
+                    None => {
+                        return Val::new_err(
+                            "format_duration() must be called on a number of seconds",
+                        )
+                    }
+                };
+                match nanos {
+                    Some(nanos) if iso => Val::new_str(format_duration_iso(nanos).as_str()),
+                    Some(nanos) => Val::new_str(format_duration_short(nanos).as_str()),
+                    None => Val::new_err("format_duration() duration out of range"),
+                }
+            }
+            "parse_bytes" => match self.val.get_val() {
+                ValType::String(text) => match parse_bytes(text) {
+                    Some(bytes) => bytes,
+                    None => Val::new_err(
+                        format!("parse_bytes() unable to parse \"{}\" as a size", text).as_str(),
+                    ),
+                },
+                ValType::Int(_) | ValType::Float64(_) | ValType::Decimal(_) => self.val.clone(),
+                _ => Val::new_err("parse_bytes() must be called on a string"),
+            },
+            "format_bytes" => {
+                let mut si = false;
+                for arg in args {
+                    let (keyword, stmt) = match keyword_arg(arg, name) {
+                        Ok(Some(keyword_arg)) => keyword_arg,
+                        Ok(None) => {
+                            return Val::new_err("format_bytes() only takes keyword arguments")
+                        }
+                        Err(err) => return err,
+                    };
+                    match (keyword, self.eval(stmt).val.get_val()) {
+                        ("si", ValType::Bool(val)) => si = *val,
+                        ("si", _) => {
+                            return Val::new_err(
+                                ":si keyword argument in format_bytes() must be a boolean",
+                            )
+                        }
+                        _ => return Val::new_err("Invalid keyword argument in format_bytes()"),
+                    }
+                }
+                match self.val.as_f64() {
+                    Some(bytes) => Val::new_str(format_bytes(bytes, si).as_str()),
+                    None => Val::new_err("format_bytes() must be called on a number"),
+                }
+            }
+            "decimal" => match self.val.get_val() {
+                ValType::String(val) => match BigDecimal::from_str(val.trim()) {
+                    Ok(val) => Val::new_decimal(val),
+                    Err(_) => Val::new_err("unable to parse number"),
+                },
+                _ => match self.val.as_decimal() {
+                    Some(val) => Val::new_decimal(val),
+                    None => Val::new_err("decimal() must be called on a number or string"),
+                },
+            },
+            "round" => {
+                let mut digits = 0;
+                let mut mode = RoundingMode::HalfUp;
+                for arg in args {
+                    match keyword_arg(arg, name) {
+                        Err(err) => return err,
+                        Ok(Some((keyword, stmt))) => {
+                            if keyword == "digits" {
+                                match self.eval_i64(stmt) {
+                                    Some(val) => digits = val,
+                                    None => return Val::new_err(
+                                        ":digits keyword argument in round() must be an integer",
+                                    ),
+                                }
+                                continue;
+                            }
+                            if keyword != "mode" {
+                                return Val::new_err("Invalid keyword argument in round()");
+                            }
+                            let val = self.eval(stmt).val;
+                            mode = match val.get_val() {
+                                ValType::String(val) => match parse_rounding_mode(val) {
+                                    Some(mode) => mode,
+                                    None => return Val::new_err(
+                                        ":mode keyword argument in round() must be half_up, half_even, half_down, up, down, ceiling or floor",
+                                    ),
+                                },
+                                _ => {
+                                    return Val::new_err(
+                                        ":mode keyword argument in round() must be a string",
+                                    )
+                                }
+                            };
+                        }
+                        Ok(None) => match self.eval_i64(arg) {
+                            Some(val) => digits = val,
+                            None => return Val::new_err("round() digits must be an integer"),
+                        },
+                    }
+                }
+                // More digits would build huge decimals without changing the result
+                if digits.abs() > MAX_ROUND_DIGITS {
+                    return Val::new_err(
+                        format!(
+                            "round() digits must be from -{} to {}",
+                            MAX_ROUND_DIGITS, MAX_ROUND_DIGITS
+                        )
+                        .as_str(),
+                    );
+                }
+
+                match self.val.get_val() {
+                    ValType::Int(val) => {
+                        let rounded = BigDecimal::from(*val).with_scale_round(digits, mode);
+                        match rounded.to_i64() {
+                            Some(rounded) => Val::new_int(rounded),
+                            None => Val::new_f64(rounded.to_f64().unwrap_or(f64::NAN)),
+                        }
+                    }
+                    // Floats are rounded from their shortest text, so 2.675 rounds up to 2.68
+                    ValType::Float64(_) => match self.val.as_decimal() {
+                        Some(val) => Val::new_f64(
+                            val.with_scale_round(digits, mode)
+                                .to_f64()
+                                .unwrap_or(f64::NAN),
+                        ),
+                        None => self.val.clone(),
+                    },
+                    ValType::Decimal(val) => Val::new_decimal(val.with_scale_round(digits, mode)),
+                    _ => Val::new_err("round() must be called on a number"),
+                }
+            }
+            "floor" | "ceil" => match self.val.get_val() {
+                ValType::Int(_) => self.val.clone(),
+                ValType::Float64(val) if name == "floor" => Val::new_f64(val.floor()),
+                ValType::Float64(val) => Val::new_f64(val.ceil()),
+                ValType::Decimal(val) if name == "floor" => {
+                    Val::new_decimal(val.with_scale_round(0, RoundingMode::Floor))
+                }
+                ValType::Decimal(val) => {
+                    Val::new_decimal(val.with_scale_round(0, RoundingMode::Ceiling))
+                }
+                _ => Val::new_err(format!("{}() must be called on a number", name).as_str()),
+            },
+            "sqrt" | "ln" | "log10" | "exp" | "sin" | "cos" | "tan" | "asin" | "acos" | "atan" => {
+                let val = match self.val.as_f64() {
+                    Some(val) => val,
+                    None => {
+                        return Val::new_err(
+                            format!("{}() must be called on a number", name).as_str(),
+                        )
+                    }
+                };
+                Val::new_f64(match name {
+                    "sqrt" => val.sqrt(),
+                    "ln" => val.ln(),
+                    "log10" => val.log10(),
+                    "exp" => val.exp(),
+                    "sin" => val.sin(),
+                    "cos" => val.cos(),
+                    "tan" => val.tan(),
+                    "asin" => val.asin(),
+                    "acos" => val.acos(),
+                    _ => val.atan(),
+                })
+            }
+            "log" | "atan2" => {
+                let val = match self.val.as_f64() {
+                    Some(val) => val,
+                    None => {
+                        return Val::new_err(
+                            format!("{}() must be called on a number", name).as_str(),
+                        )
+                    }
+                };
+                if name == "log" && args.is_empty() {
+                    return Val::new_f64(val.ln());
+                }
+                if args.len() != 1 {
+                    return Val::new_err(
+                        format!("{}() must be called with 1 argument", name).as_str(),
+                    );
+                }
+                let arg = match self.eval(&args[0]).val.as_f64() {
+                    Some(arg) => arg,
+                    None if name == "log" => return Val::new_err("log() base must be a number"),
+                    None => return Val::new_err("atan2() x must be a number"),
+                };
+                if name == "log" {
+                    Val::new_f64(val.log(arg))
+                } else {
+                    Val::new_f64(val.atan2(arg))
+                }
+            }
+            "pow" => {
+                if self.val.as_f64().is_none() {
+                    return Val::new_err("pow() must be called on a number");
+                }
+                if args.len() != 1 {
+                    return Val::new_err("pow() must be called with 1 argument");
+                }
+                let exp = self.eval(&args[0]).val;
+                if exp.as_f64().is_none() {
+                    return Val::new_err("pow() exponent must be a number");
+                }
+                num_pow(&self.val, &exp, self.decimal)
+            }
+            "clamp" => {
+                if self.val.as_f64().is_none() {
+                    return Val::new_err("clamp() must be called on a number");
+                }
+                if args.len() != 2 {
+                    return Val::new_err("clamp() must be called with 2 arguments");
+                }
+                let low = self.eval(&args[0]).val;
+                let high = self.eval(&args[1]).val;
+                if low.as_f64().is_none() || high.as_f64().is_none() {
+                    return Val::new_err("clamp() bounds must be numbers");
+                }
+                if low > high {
+                    return Val::new_err(
+                        "clamp() lower bound must not be greater than the upper bound",
+                    );
+                }
+                match self.val.get_val() {
+                    ValType::Float64(val) if val.is_nan() => self.val.clone(),
+                    _ if self.val < low => low,
+                    _ if self.val > high => high,
+                    _ => self.val.clone(),
+                }
+            }
+            "sign" => match self.val.get_val() {
+                ValType::Int(val) => Val::new_int(val.signum()),
+                ValType::Float64(val) if val.is_nan() => self.val.clone(),
+                ValType::Float64(val) if *val == 0.0 => Val::new_int(0),
+                ValType::Float64(val) => Val::new_int(val.signum() as i64),
+                ValType::Decimal(val) => match val.sign() {
+                    Sign::Minus => Val::new_int(-1),
+                    Sign::NoSign => Val::new_int(0),
+                    Sign::Plus => Val::new_int(1),
+                },
+                _ => Val::new_err("sign() must be called on a number"),
+            },
+            "is_nan" => match self.val.get_val() {
+                ValType::Float64(val) => Val::new_bool(val.is_nan()),
+                _ => Val::new_bool(false),
+            },
+            "is_finite" => match self.val.get_val() {
+                ValType::Int(_) | ValType::Decimal(_) => Val::new_bool(true),
+                ValType::Float64(val) => Val::new_bool(val.is_finite()),
+                _ => Val::new_bool(false),
+            },
+            "pi" => Val::new_f64(std::f64::consts::PI),
+            "e" => Val::new_f64(std::f64::consts::E),
             "transpose" => match self.val.get_val() {
                 ValType::List(val) => {
                     if val.len() == 0 {
@@ -1300,14 +2294,30 @@ impl EvalCtx {
     }
 }
 
+/// Splits a keyword argument such as `:digits 2` into its keyword and value,
+/// or returns None for a positional argument.
+fn keyword_arg<'a>(arg: &'a AstNode, name: &str) -> Result<Option<(&'a str, &'a AstNode)>, Val> {
+    match arg.get_type() {
+        AstNodeType::KeywordArgument(keyword, stmt) => match keyword.get_type() {
+            AstNodeType::Identifier(keyword) => Ok(Some((keyword.as_str(), stmt))),
+            _ => Err(Val::new_err(
+                format!("Invalid keyword argument in {}()", name).as_str(),
+            )),
+        },
+        _ => Ok(None),
+    }
+}
+
 fn matches_type(val: &Val, typ: &Val) -> bool {
     match typ.get_val() {
         ValType::IntType => match val.get_val() {
+            ValType::Int(_) => true,
             ValType::Float64(val) => *val == val.floor(),
+            ValType::Decimal(val) => val.is_integer(),
             _ => false,
         },
         ValType::FloatType => match val.get_val() {
-            ValType::Float64(_) => true,
+            ValType::Int(_) | ValType::Float64(_) | ValType::Decimal(_) => true,
             _ => false,
         },
         ValType::AnyType => true,
@@ -1369,38 +2379,870 @@ fn write_csv_to_val(val: &Val, delimiter: u8, fcn_name: &str) -> Val {
     let mut writer = csv::WriterBuilder::new()
         .delimiter(delimiter)
         .from_writer(&mut buffer);
-    match val.get_val() {
-        ValType::List(rows) => {
-            for row in rows {
-                let mut record = csv::StringRecord::new();
-                match row.get_val() {
-                    ValType::List(row) => {
-                        for elem in row {
-                            match elem.get_val() {
-                                ValType::String(val) => record.push_field(val),
-                                _ => {
-                                    let mut buf = Vec::<u8>::new();
-                                    elem.write_to_str(&mut buf, 0, false).unwrap();
-                                    let buf = std::str::from_utf8(buf.as_slice()).unwrap();
-                                    record.push_field(buf);
-                                }
-                            }
-                        }
+    let rows = match val.get_val() {
+        ValType::List(rows) => rows,
+        _ => return Val::new_err(format!("{}() must be called on a list", fcn_name).as_str()),
+    };
+
+    if rows
+        .iter()
+        .all(|row| matches!(row.get_val(), ValType::List(_)))
+    {
+        for row in rows {
+            if let ValType::List(row) = row.get_val() {
+                if writer.write_record(row.iter().map(val_text)).is_err() {
+                    return Val::new_err(
+                        format!("{}() must be called on rows of the same length", fcn_name)
+                            .as_str(),
+                    );
+                }
+            }
+        }
+    } else if rows
+        .iter()
+        .all(|row| matches!(row.get_val(), ValType::Map(_)))
+    {
+        // The header is the union of the keys, and missing keys are empty fields
+        let mut columns = OrderedMap::new();
+        for row in rows {
+            if let ValType::Map(row) = row.get_val() {
+                for key in row.keys() {
+                    columns.insert(&key, &Val::new_null());
+                }
+            }
+        }
+        let columns = columns.keys();
+        writer.write_record(columns.iter().map(val_text)).unwrap();
+        for row in rows {
+            if let ValType::Map(row) = row.get_val() {
+                let record = columns.iter().map(|column| {
+                    row.get(column)
+                        .map(|field| val_text(&field))
+                        .unwrap_or_default()
+                });
+                writer.write_record(record).unwrap();
+            }
+        }
+    } else {
+        return Val::new_err(
+            format!(
+                "{}() must be called on a list of lists or a list of maps",
+                fcn_name
+            )
+            .as_str(),
+        );
+    }
+
+    drop(writer);
+
+    Val::new_bytes(buffer)
+}
+
+/// Converts a CSV field that looks like a number or boolean, and empty
+/// fields to null.
+fn infer_csv_field(field: &str, decimal: bool) -> Val {
+    match field {
+        "" => Val::new_null(),
+        "true" => Val::new_bool(true),
+        "false" => Val::new_bool(false),
+        _ if field.starts_with('+') => Val::new_str(field),
+        _ => match Val::parse_number(field, decimal) {
+            Some(num) if num.as_f64().is_some_and(f64::is_finite) => num,
+            _ => Val::new_str(field),
+        },
+    }
+}
+
+impl EvalCtx {
+    /// The elements of the list a statistics function is called on, or the
+    /// keys of the elements when there is a key expression.
+    fn eval_stat_values(&self, name: &str, key_expr: Option<&AstNode>) -> Result<Vec<Val>, Val> {
+        let list = match self.val.get_val() {
+            ValType::List(list) => list,
+            _ => {
+                return Err(Val::new_err(
+                    format!("{}() must be called on a list", name).as_str(),
+                ))
+            }
+        };
+        Ok(match key_expr {
+            None => list.clone(),
+            Some(key_expr) => list
+                .iter()
+                .map(|elem| self.with_val(elem.clone()).eval(key_expr).val)
+                .collect(),
+        })
+    }
+
+    /// Divides a total by a count, keeping integers and decimals exact when
+    /// the division allows it.
+    fn exact_div(&self, total: &Val, count: i64) -> Val {
+        num_op(
+            total,
+            &Val::new_int(count),
+            self.decimal,
+            |left, right| match left.checked_rem(right) {
+                Some(0) => left.checked_div(right),
+                _ => None,
+            },
+            |left, right| Some(left / right),
+            |left, right| left / right,
+        )
+        .unwrap()
+    }
+
+    /// Compiles the regex that is the first argument of a regex function,
+    /// with any `:flags`, and returns it along with the other positional
+    /// arguments.
+    fn eval_regex_args<'a>(
+        &self,
+        name: &str,
+        args: &'a [AstNode],
+    ) -> Result<(Regex, Vec<&'a AstNode>), Val> {
+        let mut flags = String::new();
+        let mut positional = Vec::<&AstNode>::new();
+        for arg in args {
+            match keyword_arg(arg, name)? {
+                Some((keyword, stmt)) => {
+                    if keyword != "flags" {
+                        return Err(Val::new_err(
+                            format!("Invalid keyword argument in {}()", name).as_str(),
+                        ));
                     }
-                    _ => {
-                        return Val::new_err(
-                            format!("{}() must be called on a list of lists", fcn_name).as_str(),
-                        )
+                    match self.eval(stmt).val.get_val() {
+                        ValType::String(val) if val.chars().all(|c| "imsx".contains(c)) => {
+                            flags = val.clone()
+                        }
+                        _ => {
+                            return Err(Val::new_err(
+                                format!(
+                                ":flags keyword argument in {}() must be a string of i, m, s or x",
+                                name
+                            )
+                                .as_str(),
+                            ))
+                        }
                     }
                 }
+                None => positional.push(arg),
+            }
+        }
 
-                writer.write_record(record.iter()).unwrap();
+        if positional.is_empty() {
+            return Err(Val::new_err(
+                format!("{}() must be called with a regex", name).as_str(),
+            ));
+        }
+        let pattern_node = positional.remove(0);
+        let pattern = match self.eval(pattern_node).val.get_val() {
+            ValType::String(pattern) if flags.is_empty() => pattern.clone(),
+            ValType::String(pattern) => format!("(?{}){}", flags, pattern),
+            _ => {
+                return Err(Val::new_err(
+                    format!("{}() regex must be a string", name).as_str(),
+                ))
             }
+        };
+        match pattern_node.get_regex(&pattern) {
+            Ok(regex) => Ok((regex, positional)),
+            Err(err) => Err(Val::new_err(
+                format!("Invalid regex in {}(): {}", name, err).as_str(),
+            )),
         }
-        _ => return Val::new_err(format!("{}() must be called on a list", fcn_name).as_str()),
     }
+}
 
-    drop(writer);
+/// The value at a fraction of the way through a sorted list of numbers,
+/// interpolating linearly between neighbouring values.
+fn interpolate_sorted(sorted: &[Val], fraction: f64) -> Val {
+    let rank = fraction * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let weight = rank - lower as f64;
+    if weight == 0.0 {
+        return sorted[lower].clone();
+    }
+    let lower_val = sorted[lower].as_f64().unwrap();
+    let upper_val = sorted[lower + 1].as_f64().unwrap();
+    Val::new_f64(lower_val + (upper_val - lower_val) * weight)
+}
 
-    Val::new_bytes(buffer)
+/// Most bins `histogram()` and groups `quantiles()` split numbers into.
+const MAX_STAT_GROUPS: i64 = 10_000;
+
+/// Counts numbers in equal-width bins from the smallest to the largest
+/// number, with the largest number in the last bin.
+fn histogram(values: &[Val], bins: usize) -> Val {
+    let values = values
+        .iter()
+        .map(|val| val.as_f64().unwrap())
+        .collect::<Vec<_>>();
+    // NaN and infinity have no bin and would make every bin infinitely wide
+    if values.iter().any(|val| !val.is_finite()) {
+        return Val::new_err("histogram() can't be called on NaN or infinity");
+    }
+    if values.is_empty() {
+        return Val::new_list(Vec::new());
+    }
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let bins = if min == max { 1 } else { bins };
+    let width = (max - min) / bins as f64;
+
+    let mut counts = vec![0; bins];
+    for val in values {
+        let idx = if width > 0.0 {
+            ((val - min) / width) as usize
+        } else {
+            0
+        };
+        counts[idx.min(bins - 1)] += 1;
+    }
+    Val::new_list(
+        counts
+            .iter()
+            .enumerate()
+            .map(|(idx, count)| {
+                let end = if idx == bins - 1 {
+                    max
+                } else {
+                    min + width * (idx + 1) as f64
+                };
+                Val::new_map(OrderedMap::from_kv_pair_slice(&[
+                    (
+                        Val::new_str("start"),
+                        Val::new_f64(min + width * idx as f64),
+                    ),
+                    (Val::new_str("end"), Val::new_f64(end)),
+                    (Val::new_str("count"), Val::new_int(*count)),
+                ]))
+            })
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// The groups of a regex match after the whole match, with null for groups
+/// that didn't take part.
+fn regex_groups(captures: &regex::Captures) -> Vec<Val> {
+    captures
+        .iter()
+        .skip(1)
+        .map(|group| match group {
+            Some(group) => Val::new_str(group.as_str()),
+            None => Val::new_null(),
+        })
+        .collect()
+}
+
+/// The result of `match()`. Offsets and lengths count characters rather than
+/// bytes.
+fn regex_match_to_val(regex: &Regex, text: &str, captures: &regex::Captures) -> Val {
+    let whole = captures.get(0).unwrap();
+    let mut named = OrderedMap::new();
+    for name in regex.capture_names().flatten() {
+        let val = match captures.name(name) {
+            Some(group) => Val::new_str(group.as_str()),
+            None => Val::new_null(),
+        };
+        named.insert(&Val::new_str(name), &val);
+    }
+    Val::new_map(OrderedMap::from_kv_pair_slice(&[
+        (
+            Val::new_str("offset"),
+            Val::new_int(text[..whole.start()].chars().count() as i64),
+        ),
+        (
+            Val::new_str("length"),
+            Val::new_int(whole.as_str().chars().count() as i64),
+        ),
+        (Val::new_str("string"), Val::new_str(whole.as_str())),
+        (
+            Val::new_str("groups"),
+            Val::new_list(regex_groups(captures)),
+        ),
+        (Val::new_str("captures"), Val::new_map(named)),
+    ]))
+}
+
+/// Parses a datetime in ISO 8601 or RFC 2822 format. Datetimes without an
+/// offset are read as UTC.
+fn parse_datetime(text: &str) -> Option<DateTime<FixedOffset>> {
+    let text = text.trim();
+    if let Ok(datetime) = text.parse::<DateTime<FixedOffset>>() {
+        return Some(datetime);
+    }
+    if let Ok(datetime) = DateTime::parse_from_rfc2822(text) {
+        return Some(datetime);
+    }
+    for format in [
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M",
+    ] {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(text, format) {
+            return Some(datetime.and_utc().fixed_offset());
+        }
+    }
+    parse_datetime_with_format(text, "%Y-%m-%d")
+}
+
+/// Parses a datetime with a strftime format, which might only have a date
+/// or leave out the offset.
+fn parse_datetime_with_format(text: &str, format: &str) -> Option<DateTime<FixedOffset>> {
+    if let Ok(datetime) = DateTime::parse_from_str(text, format) {
+        return Some(datetime);
+    }
+    if let Ok(datetime) = NaiveDateTime::parse_from_str(text, format) {
+        return Some(datetime.and_utc().fixed_offset());
+    }
+    let date = NaiveDate::parse_from_str(text, format).ok()?;
+    Some(date.and_time(NaiveTime::MIN).and_utc().fixed_offset())
+}
+
+/// Converts unix seconds, or milliseconds, to a datetime in UTC.
+fn unix_to_datetime(val: &Val, millis: bool) -> Option<DateTime<FixedOffset>> {
+    let delta = match (val.get_val(), millis) {
+        (ValType::Int(millis), true) => TimeDelta::try_milliseconds(*millis)?,
+        (_, true) => seconds_to_delta(&Val::new_f64(val.as_f64()? / 1000.0))?,
+        (_, false) => seconds_to_delta(val)?,
+    };
+    let datetime = DateTime::UNIX_EPOCH.checked_add_signed(delta)?;
+    Some(datetime.fixed_offset())
+}
+
+const NANOS_PER_SEC: i128 = 1_000_000_000;
+const NANOS_PER_MINUTE: i128 = 60 * NANOS_PER_SEC;
+const NANOS_PER_HOUR: i128 = 60 * NANOS_PER_MINUTE;
+const NANOS_PER_DAY: i128 = 24 * NANOS_PER_HOUR;
+
+/// Parses a duration such as "90s", "1.5h", "1h 30m" or ISO 8601 "P1DT2H"
+/// as nanoseconds. The units are ns, us, ms, s, m, h, d and w, and a bare
+/// number is seconds.
+fn parse_duration_nanos(text: &str) -> Option<i64> {
+    let text = text.trim();
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(text) => (true, text),
+        None => (false, text),
+    };
+    let total = match text.strip_prefix('P') {
+        // Years and months aren't supported since their length varies
+        Some(iso) => {
+            let (date, time) = iso.split_once('T').unwrap_or((iso, ""));
+            if iso.is_empty() || iso.ends_with('T') {
+                return None;
+            }
+            let date = sum_duration_parts(date, |unit| match unit {
+                "W" => Some(7 * NANOS_PER_DAY),
+                "D" => Some(NANOS_PER_DAY),
+                _ => None,
+            })?;
+            let time = sum_duration_parts(time, |unit| match unit {
+                "H" => Some(NANOS_PER_HOUR),
+                "M" => Some(NANOS_PER_MINUTE),
+                "S" => Some(NANOS_PER_SEC),
+                _ => None,
+            })?;
+            date.checked_add(time)?
+        }
+        None if text.is_empty() => return None,
+        // A bare number is a number of seconds
+        None if text.chars().all(|c| c.is_ascii_digit() || c == '.') => {
+            sum_duration_parts(text, |_| Some(NANOS_PER_SEC))?
+        }
+        None => sum_duration_parts(text, |unit| match unit {
+            "ns" => Some(1),
+            "us" | "µs" => Some(1_000),
+            "ms" => Some(1_000_000),
+            "s" => Some(NANOS_PER_SEC),
+            "m" => Some(NANOS_PER_MINUTE),
+            "h" => Some(NANOS_PER_HOUR),
+            "d" => Some(NANOS_PER_DAY),
+            "w" => Some(7 * NANOS_PER_DAY),
+            _ => None,
+        })?,
+    };
+    i64::try_from(if negative { -total } else { total }).ok()
+}
+
+/// Adds up the nanoseconds in parts like "1h30m", where `unit_nanos` gives
+/// the size of each unit.
+fn sum_duration_parts(text: &str, unit_nanos: impl Fn(&str) -> Option<i128>) -> Option<i128> {
+    let mut total: i128 = 0;
+    let mut rest = text;
+    while !rest.is_empty() {
+        let num_len = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let (num, after_num) = rest.split_at(num_len);
+        let after_num = after_num.trim_start();
+        let unit_len = after_num
+            .find(|c: char| !c.is_alphabetic())
+            .unwrap_or(after_num.len());
+        let (unit, after_unit) = after_num.split_at(unit_len);
+        let unit_nanos = unit_nanos(unit)?;
+
+        // Keep fractions exact by scaling the digits as an integer
+        let (whole, fraction) = num.split_once('.').unwrap_or((num, ""));
+        if whole.is_empty() && fraction.is_empty() {
+            return None;
+        }
+        let digits = format!("{}{}", whole, fraction);
+        let mantissa = digits.parse::<i128>().ok()?;
+        let scale = 10i128.checked_pow(fraction.len() as u32)?;
+        total = total.checked_add(mantissa.checked_mul(unit_nanos)? / scale)?;
+        rest = after_unit.trim_start();
+    }
+    Some(total)
+}
+
+/// Formats nanoseconds as a duration like "1d2h30m" or "250ms".
+fn format_duration_short(nanos: i64) -> String {
+    let sign = if nanos < 0 { "-" } else { "" };
+    let nanos = (nanos as i128).abs();
+    if nanos == 0 {
+        return "0s".to_string();
+    }
+    if nanos < NANOS_PER_SEC {
+        let (unit, unit_nanos) = if nanos >= 1_000_000 {
+            ("ms", 1_000_000)
+        } else if nanos >= 1_000 {
+            ("us", 1_000)
+        } else {
+            ("ns", 1)
+        };
+        return format!("{}{}{}", sign, format_fraction(nanos, unit_nanos), unit);
+    }
+
+    let mut result = sign.to_string();
+    let mut rest = nanos;
+    for (unit, unit_nanos) in [
+        ("d", NANOS_PER_DAY),
+        ("h", NANOS_PER_HOUR),
+        ("m", NANOS_PER_MINUTE),
+    ] {
+        if rest >= unit_nanos {
+            result.push_str(format!("{}{}", rest / unit_nanos, unit).as_str());
+            rest %= unit_nanos;
+        }
+    }
+    if rest > 0 {
+        result.push_str(format!("{}s", format_fraction(rest, NANOS_PER_SEC)).as_str());
+    }
+    result
+}
+
+/// Formats nanoseconds as an ISO 8601 duration like "P1DT2H30M".
+fn format_duration_iso(nanos: i64) -> String {
+    let sign = if nanos < 0 { "-" } else { "" };
+    let nanos = (nanos as i128).abs();
+    let days = nanos / NANOS_PER_DAY;
+    let hours = nanos % NANOS_PER_DAY / NANOS_PER_HOUR;
+    let minutes = nanos % NANOS_PER_HOUR / NANOS_PER_MINUTE;
+    let seconds = nanos % NANOS_PER_MINUTE;
+
+    let mut result = format!("{}P", sign);
+    if days > 0 {
+        result.push_str(format!("{}D", days).as_str());
+    }
+    if hours > 0 || minutes > 0 || seconds > 0 || days == 0 {
+        result.push('T');
+    }
+    if hours > 0 {
+        result.push_str(format!("{}H", hours).as_str());
+    }
+    if minutes > 0 {
+        result.push_str(format!("{}M", minutes).as_str());
+    }
+    if seconds > 0 || nanos == 0 {
+        result.push_str(format!("{}S", format_fraction(seconds, NANOS_PER_SEC)).as_str());
+    }
+    result
+}
+
+/// Formats `val / unit` without trailing zeros in the fraction, where `unit`
+/// is a power of ten.
+fn format_fraction(val: i128, unit: i128) -> String {
+    let whole = val / unit;
+    let fraction = val % unit;
+    if fraction == 0 {
+        return whole.to_string();
+    }
+    let width = unit.ilog10() as usize;
+    let fraction = format!("{:0width$}", fraction, width = width);
+    format!("{}.{}", whole, fraction.trim_end_matches('0'))
+}
+
+/// The number of bytes in a size like "1.5GiB", "10 MB" or "512". Units
+/// ending in "iB" and single letters like "K" are powers of 1024, and units
+/// like "KB" are powers of 1000.
+fn parse_bytes(text: &str) -> Option<Val> {
+    let text = text.trim();
+    let num_len = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (num, unit) = text.split_at(num_len);
+    let unit = match unit.trim().to_lowercase().as_str() {
+        "byte" | "bytes" => "b".to_string(),
+        unit => unit.to_string(),
+    };
+    let (prefix, suffix) = match unit.char_indices().nth(1) {
+        Some((idx, _)) => unit.split_at(idx),
+        None => (unit.as_str(), ""),
+    };
+    let power = match prefix {
+        "" | "b" if suffix.is_empty() => 0,
+        "k" => 1,
+        "m" => 2,
+        "g" => 3,
+        "t" => 4,
+        "p" => 5,
+        "e" => 6,
+        _ => return None,
+    };
+    let base: i64 = match suffix {
+        "" | "ib" | "i" => 1024,
+        "b" => 1000,
+        _ => return None,
+    };
+    let multiplier = base.checked_pow(power)?;
+
+    if let Some(bytes) = num
+        .parse::<i64>()
+        .ok()
+        .and_then(|num| num.checked_mul(multiplier))
+    {
+        return Some(Val::new_int(bytes));
+    }
+    let bytes = (num.parse::<f64>().ok()? * multiplier as f64).round();
+    if bytes.abs() < MAX_EXACT_FLOAT_INT {
+        Some(Val::new_int(bytes as i64))
+    } else {
+        Some(Val::new_f64(bytes))
+    }
+}
+
+/// Formats a number of bytes like "1.5GiB", or "1.5GB" with powers of 1000.
+fn format_bytes(bytes: f64, si: bool) -> String {
+    let (base, units) = if si {
+        (1000.0, ["B", "kB", "MB", "GB", "TB", "PB", "EB"])
+    } else {
+        (1024.0, ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"])
+    };
+    let mut val = bytes;
+    let mut idx = 0;
+    while val.abs() >= base && idx < units.len() - 1 {
+        val /= base;
+        idx += 1;
+    }
+    let text = format!("{:.1}", val);
+    format!("{}{}", text.trim_end_matches(".0"), units[idx])
+}
+
+/// Parses the `:mode` keyword argument of `round()`.
+fn parse_rounding_mode(name: &str) -> Option<RoundingMode> {
+    match name {
+        "half_up" => Some(RoundingMode::HalfUp),
+        "half_even" => Some(RoundingMode::HalfEven),
+        "half_down" => Some(RoundingMode::HalfDown),
+        "up" => Some(RoundingMode::Up),
+        "down" => Some(RoundingMode::Down),
+        "ceiling" => Some(RoundingMode::Ceiling),
+        "floor" => Some(RoundingMode::Floor),
+        _ => None,
+    }
+}
+
+/// Options for `to_text_table()`.
+struct TextTableOptions {
+    border: bool,
+    max_width: Option<usize>,
+}
+
+/// Renders a list of maps, or a list of lists whose first row is the header,
+/// as aligned columns, the inverse of `from_text_table()`. Columns of numbers
+/// are right-aligned.
+fn write_text_table_to_val(val: &Val, opts: &TextTableOptions) -> Val {
+    let (header, rows) = match table_rows(val, "to_text_table") {
+        Err(err) => return err,
+        Ok(table) => table,
+    };
+    if header.is_empty() {
+        return Val::new_str("");
+    }
+
+    let numeric = numeric_columns(header.len(), &rows);
+
+    let truncate = |text: String| match opts.max_width {
+        Some(max_width) if text.width() > max_width => {
+            let mut truncated = String::new();
+            for ch in text.chars() {
+                if truncated.width() + ch.width().unwrap_or(0) + 1 > max_width {
+                    break;
+                }
+                truncated.push(ch);
+            }
+            truncated.push('…');
+            truncated
+        }
+        _ => text,
+    };
+    let mut cells = vec![header
+        .iter()
+        .map(|cell| truncate(table_cell_text(cell)))
+        .collect::<Vec<_>>()];
+    for row in &rows {
+        cells.push(
+            (0..header.len())
+                .map(|idx| truncate(row.get(idx).map(table_cell_text).unwrap_or_default()))
+                .collect(),
+        );
+    }
+
+    let mut widths = vec![0; header.len()];
+    for row in &cells {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.width());
+        }
+    }
+
+    let (separator, left, right) = if opts.border {
+        (" │ ", "│ ", " │")
+    } else {
+        ("  ", "", "")
+    };
+    let rule = |left: &str, middle: &str, right: &str| {
+        let lines = widths
+            .iter()
+            .map(|width| "─".repeat(width + 2))
+            .collect::<Vec<_>>();
+        format!("{}{}{}\n", left, lines.join(middle), right)
+    };
+
+    let mut result = String::new();
+    if opts.border {
+        result.push_str(&rule("┌", "┬", "┐"));
+    }
+    for (row_idx, row) in cells.iter().enumerate() {
+        let mut line = String::from(left);
+        for (idx, cell) in row.iter().enumerate() {
+            if idx > 0 {
+                line.push_str(separator);
+            }
+            let padding = " ".repeat(widths[idx] - cell.width());
+            if numeric[idx] {
+                line.push_str(&padding);
+                line.push_str(cell);
+            } else {
+                line.push_str(cell);
+                line.push_str(&padding);
+            }
+        }
+        line.push_str(right);
+        result.push_str(line.trim_end());
+        result.push('\n');
+        if row_idx == 0 && opts.border {
+            result.push_str(&rule("├", "┼", "┤"));
+        }
+    }
+    if opts.border {
+        result.push_str(&rule("└", "┴", "┘"));
+    }
+    Val::new_str(result.as_str())
+}
+
+/// Renders a list of maps or lists as a GitHub-flavored Markdown table.
+fn write_markdown_table_to_val(val: &Val) -> Val {
+    let (header, rows) = match table_rows(val, "to_markdown_table") {
+        Err(err) => return err,
+        Ok(table) => table,
+    };
+    if header.is_empty() {
+        return Val::new_str("");
+    }
+
+    let markdown_row = |row: &[Val]| {
+        let cells = (0..header.len())
+            .map(|idx| escape_markdown(&row.get(idx).map(table_cell_text).unwrap_or_default()))
+            .collect::<Vec<_>>();
+        format!("| {} |\n", cells.join(" | "))
+    };
+    let alignments = numeric_columns(header.len(), &rows)
+        .iter()
+        .map(|numeric| if *numeric { "---:" } else { "---" })
+        .collect::<Vec<_>>();
+
+    let mut result = markdown_row(&header);
+    result.push_str(&format!("| {} |\n", alignments.join(" | ")));
+    for row in &rows {
+        result.push_str(&markdown_row(row));
+    }
+    Val::new_str(result.as_str())
+}
+
+/// Escapes the characters that Markdown would otherwise format, along with
+/// the pipes and newlines that would break up a table row.
+fn escape_markdown(text: &str) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' | '|' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' => {
+                result.push('\\');
+                result.push(ch);
+            }
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\r' | '\n' => result.push_str("<br>"),
+            _ => result.push(ch),
+        }
+    }
+    result
+}
+
+/// Renders a list of maps or lists as an HTML `<table>`.
+fn write_html_table_to_val(val: &Val) -> Val {
+    let (header, rows) = match table_rows(val, "to_html_table") {
+        Err(err) => return err,
+        Ok(table) => table,
+    };
+    if header.is_empty() {
+        return Val::new_str("");
+    }
+    let numeric = numeric_columns(header.len(), &rows);
+
+    let html_row = |row: &[Val], tag: &str| {
+        let mut line = String::from("    <tr>");
+        for (idx, numeric) in numeric.iter().enumerate() {
+            let cell = escape_html(&row.get(idx).map(table_cell_text).unwrap_or_default());
+            if *numeric {
+                line.push_str(&format!("<{} align=\"right\">{}</{}>", tag, cell, tag));
+            } else {
+                line.push_str(&format!("<{}>{}</{}>", tag, cell, tag));
+            }
+        }
+        line.push_str("</tr>\n");
+        line
+    };
+
+    let mut result = String::from("<table>\n  <thead>\n");
+    result.push_str(&html_row(&header, "th"));
+    result.push_str("  </thead>\n  <tbody>\n");
+    for row in &rows {
+        result.push_str(&html_row(row, "td"));
+    }
+    result.push_str("  </tbody>\n</table>\n");
+    Val::new_str(result.as_str())
+}
+
+/// Escapes text for use in HTML elements and attributes.
+fn escape_html(text: &str) -> String {
+    let mut result = String::new();
+    for ch in text.chars() {
+        match ch {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&#39;"),
+            _ => result.push(ch),
+        }
+    }
+    result
+}
+
+/// Columns where every cell below the header is a number or null, and at
+/// least one cell exists.
+fn numeric_columns(len: usize, rows: &[Vec<Val>]) -> Vec<bool> {
+    (0..len)
+        .map(|idx| {
+            let mut column = rows.iter().filter_map(|row| row.get(idx)).peekable();
+            column.peek().is_some()
+                && column.all(|cell| {
+                    matches!(
+                        cell.get_val(),
+                        ValType::Int(_) | ValType::Float64(_) | ValType::Decimal(_) | ValType::Null
+                    )
+                })
+        })
+        .collect()
+}
+
+/// Splits a list of maps or a list of lists into a header row and the rows
+/// below it. The columns of a list of maps are the union of its keys, in the
+/// order they're first seen.
+fn table_rows(val: &Val, fcn_name: &str) -> Result<(Vec<Val>, Vec<Vec<Val>>), Val> {
+    let rows = match val.get_val() {
+        ValType::List(rows) => rows,
+        _ => {
+            return Err(Val::new_err(
+                format!("{}() must be called on a list", fcn_name).as_str(),
+            ))
+        }
+    };
+
+    if rows
+        .iter()
+        .all(|row| matches!(row.get_val(), ValType::List(_)))
+    {
+        let mut rows = rows.iter().filter_map(|row| match row.get_val() {
+            ValType::List(row) => Some(row.clone()),
+            _ => None,
+        });
+        let header = rows.next().unwrap_or_default();
+        return Ok((header, rows.collect()));
+    }
+
+    let mut columns = OrderedMap::new();
+    for row in rows {
+        match row.get_val() {
+            ValType::Map(row) => {
+                for key in row.keys() {
+                    columns.insert(&key, &Val::new_null());
+                }
+            }
+            _ => {
+                return Err(Val::new_err(
+                    format!("{}() must be called on a list of maps or lists", fcn_name).as_str(),
+                ))
+            }
+        }
+    }
+    let header = columns.keys();
+    let rows = rows
+        .iter()
+        .filter_map(|row| match row.get_val() {
+            ValType::Map(row) => Some(
+                header
+                    .iter()
+                    .map(|column| row.get_non_null(column))
+                    .collect(),
+            ),
+            _ => None,
+        })
+        .collect();
+    Ok((header, rows))
+}
+
+/// Strings are shown without quotes, nulls as empty cells and anything else
+/// as compact JSON.
+fn table_cell_text(val: &Val) -> String {
+    match val.get_val() {
+        ValType::Null => String::new(),
+        _ => val_text(val),
+    }
+}
+
+/// Text of a value on its own, such as in a table cell or CSV field: strings as they are,
+/// bytes as base64 like when they're nested in JSON, and anything else as
+/// compact JSON.
+fn val_text(val: &Val) -> String {
+    match val.get_val() {
+        ValType::String(val) => val.clone(),
+        ValType::Datetime(val, _) => datetime_to_iso(val),
+        ValType::Bytes(val) => STANDARD.encode(val),
+        _ => {
+            let mut buf = Vec::<u8>::new();
+            // Writing to a Vec can't fail
+            let _ = val.write_to_str(&mut buf, 0, false);
+            String::from_utf8_lossy(&buf).into_owned()
+        }
+    }
 }
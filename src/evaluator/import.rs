@@ -0,0 +1,158 @@
+use super::{EvalCtx, Module};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::ast_node::{AstNode, AstNodeType};
+use crate::parser::Parser;
+use crate::val::{Val, ValType};
+
+impl EvalCtx {
+    /// Evaluates `import "path" as alias;` or `include "path";` by evaluating
+    /// the file and adding the functions it defines to the current scope,
+    /// prefixed with `alias::` for imports.
+    pub fn eval_import(&self, path: &AstNode, alias: Option<&AstNode>) -> EvalCtx {
+        if self.abort_preview() {
+            return self.with_val(Val::new_err("import is not run in previews"));
+        }
+        let path_val = self.eval(path).val;
+        let path = match path_val.get_val() {
+            ValType::String(path) => path.as_str(),
+            _ => return self.with_val(Val::new_err("import path must be a string")),
+        };
+
+        let resolved_path = match self.resolve_import_path(path) {
+            Err(err) => return self.with_val(Val::new_err(err.as_str())),
+            Ok(resolved_path) => resolved_path,
+        };
+
+        let mut import_chain = vec![resolved_path.display().to_string()];
+        let mut module = self.module.as_ref();
+        while let Some(inner_module) = module {
+            import_chain.push(inner_module.path.display().to_string());
+            if inner_module.path == resolved_path {
+                import_chain.reverse();
+                return self.with_val(Val::new_err(
+                    format!("import cycle: {}", import_chain.join(" -> ")).as_str(),
+                ));
+            }
+            module = inner_module.parent.as_ref();
+        }
+
+        let module_ctx = match self.eval_file(&resolved_path, Val::new_null()) {
+            Err(err) => return self.with_val(Val::new_err(err.as_str())),
+            Ok(module_ctx) => module_ctx,
+        };
+
+        let prefix = match alias.map(|alias| alias.get_type()) {
+            Some(AstNodeType::Identifier(alias)) => format!("{}::", alias),
+            _ => String::new(),
+        };
+        let mut functions = self.functions.clone();
+        for (name, fcn) in module_ctx.functions {
+            functions.insert(format!("{}{}", prefix, name), fcn);
+        }
+
+        EvalCtx {
+            functions,
+            ..self.clone()
+        }
+    }
+
+    /// Evaluates the file at `path` with the current value as the input, as
+    /// done for the init file and `:load` in the REPL. The returned context
+    /// has the variables and functions the file defines in addition to the
+    /// ones in this context, and the file's result as its value.
+    pub fn with_file(&self, path: &Path) -> Result<EvalCtx, String> {
+        let file_ctx = self.eval_file(
+            &path.canonicalize().unwrap_or(path.to_path_buf()),
+            self.val.clone(),
+        )?;
+
+        let mut variables = self.variables.clone();
+        variables.extend(file_ctx.variables);
+        let mut functions = self.functions.clone();
+        functions.extend(file_ctx.functions);
+
+        Ok(EvalCtx {
+            variables,
+            functions,
+            val: file_ctx.val,
+            ..self.clone()
+        })
+    }
+
+    /// Evaluates the expression in the file at `path` with `input` as `.`.
+    fn eval_file(&self, path: &Path, input: Val) -> Result<EvalCtx, String> {
+        let text = match std::fs::read_to_string(path) {
+            Err(err) => return Err(format!("unable to read \"{}\": {}", path.display(), err)),
+            Ok(text) => text,
+        };
+
+        let file_ctx = EvalCtx {
+            variables: HashMap::new(),
+            functions: HashMap::new(),
+            module: Some(Rc::new(Module {
+                path: path.to_path_buf(),
+                parent: self.module.clone(),
+            })),
+            val: input,
+            ..self.clone()
+        };
+        let file_ctx = match Parser::new(text.as_str()).external_parse_expr() {
+            None => file_ctx,
+            Some(Err(err)) => {
+                return Err(format!(
+                    "unable to parse \"{}\": {}",
+                    path.display(),
+                    err.to_string()
+                ))
+            }
+            Some(Ok(ast)) => file_ctx.eval(&ast),
+        };
+        if let ValType::Err(err) = file_ctx.val.get_val() {
+            return Err(format!("error in \"{}\": {}", path.display(), err.msg));
+        }
+
+        Ok(file_ctx)
+    }
+
+    /// Looks for `path` relative to the directory of the importing file (or the
+    /// working directory) and then in each directory of `KJ_PATH`.
+    fn resolve_import_path(&self, path: &str) -> Result<PathBuf, String> {
+        let path = Path::new(path);
+        let mut search_dirs = Vec::<PathBuf>::new();
+        if !path.is_absolute() {
+            search_dirs.push(
+                match self.module.as_ref().and_then(|module| module.path.parent()) {
+                    None => PathBuf::from("."),
+                    Some(dir) => dir.to_path_buf(),
+                },
+            );
+            if let Some(kj_path) = std::env::var_os("KJ_PATH") {
+                search_dirs.extend(std::env::split_paths(&kj_path));
+            }
+        }
+
+        let candidates = if search_dirs.is_empty() {
+            vec![path.to_path_buf()]
+        } else {
+            search_dirs.iter().map(|dir| dir.join(path)).collect()
+        };
+        for candidate in &candidates {
+            if candidate.is_file() {
+                return Ok(candidate.canonicalize().unwrap_or(candidate.clone()));
+            }
+        }
+
+        Err(format!(
+            "unable to find \"{}\", looked in {}",
+            path.display(),
+            candidates
+                .iter()
+                .map(|candidate| format!("\"{}\"", candidate.display()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    }
+}
@@ -1,24 +1,207 @@
+use std::cell::Cell;
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use super::ast_node::{AstNode, AstNodeType};
 use super::parser::Parser;
-use super::val::{OrderedMap, Val, ValType};
+use super::val::{ErrVal, OrderedMap, Val, ValType};
+use bigdecimal::num_bigint::Sign;
+use bigdecimal::{BigDecimal, One, RoundingMode, ToPrimitive};
+use chrono::TimeDelta;
 
+mod complete;
 mod eval_fcn;
+mod import;
 
+#[derive(Clone)]
 pub struct EvalCtx {
     variables: HashMap<String, Val>,
+    functions: HashMap<String, Rc<UserFcn>>,
+    call_stack: Option<Rc<CallFrame>>,
+    preview: Option<Rc<Preview>>,
+    /// Number literals and parsed numbers with a fraction are read as exact
+    /// decimals, set with `with_decimal()`.
+    decimal: bool,
+    module: Option<Rc<Module>>,
     pub val: Val,
 }
 
+/// A function defined with `def`, along with the variables and functions that
+/// were in scope where it was defined.
+struct UserFcn {
+    name: String,
+    params: Vec<(String, Option<AstNode>)>,
+    body: AstNode,
+    variables: HashMap<String, Val>,
+    functions: HashMap<String, Rc<UserFcn>>,
+    module: Option<Rc<Module>>,
+}
+
+/// The file that the expression being evaluated was read from, along with the
+/// chain of files that imported it.
+struct Module {
+    path: PathBuf,
+    parent: Option<Rc<Module>>,
+}
+
+/// A function call that is currently being evaluated.
+struct CallFrame {
+    node: AstNode,
+    parent: Option<Rc<CallFrame>>,
+    /// Number of calls on the stack, including this one.
+    depth: usize,
+}
+
+/// Most function calls that can be nested, so that runaway recursion in a
+/// user-defined function is reported as an error rather than overflowing the
+/// stack.
+const MAX_CALL_DEPTH: usize = 1000;
+
+/// Most elements in a list or map, or bytes in a string, that a preview works
+/// on before it's stopped.
+const MAX_PREVIEW_LEN: usize = 100_000;
+
+/// Limits for evaluating a preview of the result while typing in the REPL.
+struct Preview {
+    deadline: Instant,
+    /// Set once the evaluation runs past the deadline, works on a value longer
+    /// than MAX_PREVIEW_LEN or reaches a function with side effects, after
+    /// which the result is thrown away.
+    aborted: Cell<bool>,
+}
+
 impl EvalCtx {
     pub fn new() -> Self {
         EvalCtx {
             variables: HashMap::new(),
+            functions: HashMap::new(),
+            call_stack: None,
+            preview: None,
+            decimal: false,
+            module: None,
             val: Val::new_null(),
         }
     }
 
+    pub fn with_variable(&self, name: &str, val: Val) -> EvalCtx {
+        let mut variables = self.variables.clone();
+        variables.insert(name.to_string(), val);
+
+        EvalCtx {
+            variables,
+            ..self.clone()
+        }
+    }
+
+    /// Reads number literals, and the numbers parsed by `from_json()`,
+    /// `from_ndjson()` and `from_csv()`, as exact decimals when they don't fit
+    /// in an integer.
+    pub fn with_decimal(&self, decimal: bool) -> EvalCtx {
+        EvalCtx {
+            decimal,
+            ..self.clone()
+        }
+    }
+
+    /// Sets the file the expression was read from so that imports are resolved
+    /// relative to it.
+    pub fn with_source_path(&self, path: &str) -> EvalCtx {
+        let path = PathBuf::from(path);
+        EvalCtx {
+            module: Some(Rc::new(Module {
+                path: path.canonicalize().unwrap_or(path),
+                parent: None,
+            })),
+            ..self.clone()
+        }
+    }
+
+    /// The variables in scope, sorted by name.
+    pub fn variables(&self) -> Vec<(&str, &Val)> {
+        let mut variables = self
+            .variables
+            .iter()
+            .map(|(name, val)| (name.as_str(), val))
+            .collect::<Vec<_>>();
+        variables.sort_by_key(|(name, _)| *name);
+        variables
+    }
+
+    /// A short description of the function called `name`, either the
+    /// signature of a user-defined function or the usage of a builtin.
+    pub fn describe_fcn(&self, name: &str) -> Option<String> {
+        if let Some(fcn) = self.functions.get(name) {
+            let params = fcn
+                .params
+                .iter()
+                .map(|(param, default)| match default {
+                    None => param.clone(),
+                    Some(_) => format!(":{}", param),
+                })
+                .collect::<Vec<_>>();
+            return Some(format!("def {}({})", name, params.join(", ")));
+        }
+        eval_fcn::BUILTIN_FCNS
+            .iter()
+            .find(|(builtin, _)| *builtin == name)
+            .map(|(_, usage)| usage.to_string())
+    }
+
+    /// Parses and evaluates `text` for a preview of its result. Returns None
+    /// if it doesn't parse, takes longer than `budget` to evaluate, works on a
+    /// value longer than MAX_PREVIEW_LEN or would read files or run a function
+    /// with side effects such as `in()` or `exec()`.
+    pub fn preview(&self, text: &str, budget: Duration) -> Option<Val> {
+        let node = match Parser::new(text).external_parse_expr() {
+            Some(Ok(node)) => node,
+            _ => return None,
+        };
+        let preview = Rc::new(Preview {
+            deadline: Instant::now() + budget,
+            aborted: Cell::new(false),
+        });
+        let val = EvalCtx {
+            preview: Some(preview.clone()),
+            ..self.clone()
+        }
+        .eval(&node)
+        .val;
+
+        if preview.aborted.get() {
+            None
+        } else {
+            Some(val)
+        }
+    }
+
+    /// Stops a preview, returning true if this context is evaluating one.
+    fn abort_preview(&self) -> bool {
+        match &self.preview {
+            None => false,
+            Some(preview) => {
+                preview.aborted.set(true);
+                true
+            }
+        }
+    }
+
+    /// Stops a preview that has run past its deadline or would work on a value
+    /// with `len` elements, more than MAX_PREVIEW_LEN. Returns true if this
+    /// context is evaluating a preview that has been stopped.
+    fn preview_over_budget(&self, len: usize) -> bool {
+        match &self.preview {
+            None => false,
+            Some(preview) => {
+                if len > MAX_PREVIEW_LEN || Instant::now() > preview.deadline {
+                    preview.aborted.set(true);
+                }
+                preview.aborted.get()
+            }
+        }
+    }
+
     pub fn parse_and_eval(&self, text: &str) -> EvalCtx {
         let mut parser = Parser::new(text);
         match parser.external_parse_expr() {
@@ -46,6 +229,8 @@ impl EvalCtx {
     fn eval_i64(&self, node: &AstNode) -> Option<i64> {
         let val = self.eval(node).val;
         match val.get_val() {
+            ValType::Int(val) => Some(*val),
+            ValType::Decimal(val) if val.is_integer() => val.to_i64(),
             ValType::Float64(val) => {
                 if *val == val.trunc() {
                     Some(*val as i64)
@@ -81,43 +266,147 @@ impl EvalCtx {
         Val::new_bool(callback(left_val, right_val))
     }
 
-    fn eval_f64_expr(
+    /// Evaluates an arithmetic operator with `num_op()`, or `datetime_op()`
+    /// when either side is a datetime.
+    fn eval_num_expr(
         &self,
         left: &AstNode,
         right: &AstNode,
-        left_err: &str,
-        right_err: &str,
+        op: ArithOp,
+        int_callback: impl Fn(i64, i64) -> Option<i64>,
+        decimal_callback: impl Fn(&BigDecimal, &BigDecimal) -> Option<BigDecimal>,
         callback: impl Fn(f64, f64) -> f64,
     ) -> Val {
         let left_val = self.eval(left).val;
-        let left_val = match left_val.get_val() {
-            ValType::Float64(val) => *val,
-            _ => {
-                return Val::new_err(left_err);
-            }
-        };
         let right_val = self.eval(right).val;
-        let right_val = match right_val.get_val() {
-            ValType::Float64(val) => *val,
-            _ => {
-                return Val::new_err(right_err);
-            }
-        };
-        Val::new_f64(callback(left_val, right_val))
+        if let Some(result) = datetime_op(&left_val, &right_val, op) {
+            return result;
+        }
+        if let Some(err) = num_operands_err(&left_val, &right_val, op.name()) {
+            return err;
+        }
+        num_op(
+            &left_val,
+            &right_val,
+            self.decimal,
+            int_callback,
+            decimal_callback,
+            callback,
+        )
+        .unwrap()
     }
 
-    fn with_val(&self, val: Val) -> EvalCtx {
+    /// Like `eval_num_expr()` but fails when the right side is zero.
+    fn eval_div_expr(
+        &self,
+        left: &AstNode,
+        right: &AstNode,
+        op_name: &str,
+        int_callback: impl Fn(i64, i64) -> Option<i64>,
+        decimal_callback: impl Fn(&BigDecimal, &BigDecimal) -> Option<BigDecimal>,
+        callback: impl Fn(f64, f64) -> f64,
+    ) -> Val {
+        let left_val = self.eval(left).val;
+        let right_val = self.eval(right).val;
+        if let Some(err) = num_operands_err(&left_val, &right_val, op_name) {
+            return err;
+        }
+        if right_val.as_f64() == Some(0.0) {
+            return Val::new_err("divide by zero");
+        }
+        num_op(
+            &left_val,
+            &right_val,
+            self.decimal,
+            int_callback,
+            decimal_callback,
+            callback,
+        )
+        .unwrap()
+    }
+
+    pub fn with_val(&self, val: Val) -> EvalCtx {
         EvalCtx {
-            variables: self.variables.clone(),
             val,
+            ..self.clone()
+        }
+    }
+
+    /// Pushes the call `node` on the call stack, or returns None when the
+    /// stack already has MAX_CALL_DEPTH calls.
+    fn with_call_frame(&self, node: &AstNode) -> Option<EvalCtx> {
+        let depth = self.call_stack.as_ref().map_or(0, |frame| frame.depth) + 1;
+        if depth > MAX_CALL_DEPTH {
+            return None;
+        }
+        Some(EvalCtx {
+            call_stack: Some(Rc::new(CallFrame {
+                node: node.clone(),
+                parent: self.call_stack.clone(),
+                depth,
+            })),
+            ..self.clone()
+        })
+    }
+
+    /// Names of the calls on the stack followed by `node`, outermost first.
+    fn call_trace(&self, node: &AstNode) -> Vec<String> {
+        let mut trace = Vec::<String>::new();
+        if let Some(name) = node.get_op_name() {
+            trace.push(name.to_string());
+        }
+        let mut frame = self.call_stack.as_ref();
+        while let Some(inner_frame) = frame {
+            if let Some(name) = inner_frame.node.get_op_name() {
+                trace.push(name.to_string());
+            }
+            frame = inner_frame.parent.as_ref();
+        }
+        trace.reverse();
+        trace
+    }
+
+    pub fn eval(&self, node: &AstNode) -> EvalCtx {
+        if self.preview_over_budget(0) {
+            return self.with_val(Val::new_err("preview ran out of time"));
+        }
+
+        let result = self.inner_eval(node);
+        if self.preview_over_budget(val_len(&result.val)) {
+            return self.with_val(Val::new_err("preview value is too large"));
+        }
+        // Errors are tagged with the location of the innermost node that
+        // produced them so they can be reported against the query text.
+        match (result.val.get_val(), node.get_span()) {
+            (ValType::Err(err), Some(span)) if err.span.is_none() => {
+                let trace = if err.trace.is_empty() {
+                    self.call_trace(node)
+                } else {
+                    err.trace.clone()
+                };
+                let err = Val::new(ValType::Err(ErrVal {
+                    msg: err.msg.clone(),
+                    span: Some(span),
+                    trace,
+                }));
+                result.with_val(err)
+            }
+            _ => result,
         }
     }
 
-    fn eval(&self, node: &AstNode) -> EvalCtx {
+    fn inner_eval(&self, node: &AstNode) -> EvalCtx {
         match node.get_type() {
             AstNodeType::Null => self.with_val(Val::new_null()),
             AstNodeType::Pipe(left, right) => {
                 let left_val = self.eval(left);
+                // The rest of the expression can't run without the imported
+                // functions, so a failed import is the result.
+                if let (AstNodeType::Import { .. }, ValType::Err(_)) =
+                    (left.get_type(), left_val.val.get_val())
+                {
+                    return left_val;
+                }
                 left_val.eval(right)
             }
             AstNodeType::Coalesce(left, right) => {
@@ -184,62 +473,113 @@ impl EvalCtx {
                 let right_val = self.eval(right);
                 self.with_val(Val::new_bool(left_val.val >= right_val.val))
             }
-            AstNodeType::Add(left, right) => self.with_val(self.eval_f64_expr(
+            AstNodeType::Add(left, right) => self.with_val(self.eval_num_expr(
                 left,
                 right,
-                "Left side of addition has to be a float",
-                "Right side of addition has to be a float",
+                ArithOp::Add,
+                i64::checked_add,
+                |left, right| Some(left + right),
                 |left, right| left + right,
             )),
-            AstNodeType::Subtract(left, right) => self.with_val(self.eval_f64_expr(
+            AstNodeType::Subtract(left, right) => self.with_val(self.eval_num_expr(
                 left,
                 right,
-                "Left side of subtraction has to be a float",
-                "Right side of subtraction has to be a float",
+                ArithOp::Subtract,
+                i64::checked_sub,
+                |left, right| Some(left - right),
                 |left, right| left - right,
             )),
-            AstNodeType::Multiply(left, right) => self.with_val(self.eval_f64_expr(
+            AstNodeType::Multiply(left, right) => self.with_val(self.eval_num_expr(
                 left,
                 right,
-                "Left side of multiplication has to be a float",
-                "Right side of multiplication has to be a float",
+                ArithOp::Multiply,
+                i64::checked_mul,
+                |left, right| Some(left * right),
                 |left, right| left * right,
             )),
-            AstNodeType::Divide(left, right) => {
-                let left_val = self.eval(left).val;
-                let left_val = match left_val.get_val() {
-                    ValType::Float64(val) => *val,
-                    _ => {
-                        return self
-                            .with_val(Val::new_err("Left side of division has to be a float"));
+            AstNodeType::Divide(left, right) => self.with_val(self.eval_div_expr(
+                left,
+                right,
+                "division",
+                // Only exact quotients stay integers
+                |left, right| match left.checked_rem(right) {
+                    Some(0) => left.checked_div(right),
+                    _ => None,
+                },
+                |left, right| Some(left / right),
+                |left, right| left / right,
+            )),
+            // Modulo and floor division round towards negative infinity, so
+            // `-1 % 60` is 59 and `-1 // 60` is -1
+            AstNodeType::Modulo(left, right) => self.with_val(self.eval_div_expr(
+                left,
+                right,
+                "modulo",
+                |left, right| {
+                    let rem = left.checked_rem(right)?;
+                    if rem != 0 && (rem < 0) != (right < 0) {
+                        Some(rem + right)
+                    } else {
+                        Some(rem)
                     }
-                };
-                let right_val = self.eval(right).val;
-                let right_val = match right_val.get_val() {
-                    ValType::Float64(val) => *val,
-                    _ => {
-                        return self
-                            .with_val(Val::new_err("Right side of division has to be a float"));
+                },
+                |left, right| {
+                    let rem = left % right;
+                    if rem.sign() != Sign::NoSign && rem.sign() != right.sign() {
+                        Some(rem + right)
+                    } else {
+                        Some(rem)
                     }
-                };
-                if right_val == 0.0 {
-                    self.with_val(Val::new_err("divide by zero"))
-                } else {
-                    self.with_val(Val::new_f64(left_val / right_val))
+                },
+                |left, right| {
+                    let rem = left % right;
+                    if rem != 0.0 && (rem < 0.0) != (right < 0.0) {
+                        rem + right
+                    } else {
+                        rem
+                    }
+                },
+            )),
+            AstNodeType::FloorDivide(left, right) => self.with_val(self.eval_div_expr(
+                left,
+                right,
+                "floor division",
+                |left, right| {
+                    let quot = left.checked_div(right)?;
+                    if left % right != 0 && (left < 0) != (right < 0) {
+                        Some(quot - 1)
+                    } else {
+                        Some(quot)
+                    }
+                },
+                |left, right| Some((left / right).with_scale_round(0, RoundingMode::Floor)),
+                |left, right| (left / right).floor(),
+            )),
+            AstNodeType::Power(left, right) => {
+                let left_val = self.eval(left).val;
+                let right_val = self.eval(right).val;
+                if let Some(err) = num_operands_err(&left_val, &right_val, "exponentiation") {
+                    return self.with_val(err);
                 }
+                self.with_val(num_pow(&left_val, &right_val, self.decimal))
             }
             AstNodeType::Negative(expr) => {
                 let val = self.eval(expr).val;
-                let val = match val.get_val() {
-                    ValType::Float64(val) => *val,
-                    _ => {
-                        return self.with_val(Val::new_err("Negative expression must be a number"));
-                    }
-                };
-                self.with_val(Val::new_f64(-val))
+                match val.get_val() {
+                    ValType::Int(val) => match val.checked_neg() {
+                        Some(val) => self.with_val(Val::new_int(val)),
+                        None => self.with_val(Val::new_f64(-(*val as f64))),
+                    },
+                    ValType::Float64(val) => self.with_val(Val::new_f64(-val)),
+                    ValType::Decimal(val) => self.with_val(Val::new_decimal(-val)),
+                    _ => self.with_val(Val::new_err("Negative expression must be a number")),
+                }
             }
-            AstNodeType::Integer(val) => self.with_val(Val::new_f64(*val as f64)),
-            AstNodeType::Float64(val) => self.with_val(Val::new_f64(*val)),
+            AstNodeType::Integer(val) => self.with_val(Val::new_int(*val)),
+            AstNodeType::Float64(val, text) => match Val::parse_number(text, self.decimal) {
+                Some(val) => self.with_val(val),
+                None => self.with_val(Val::new_f64(*val)),
+            },
             AstNodeType::MapLiteral(contents) => {
                 let mut map = OrderedMap::new();
                 fn helper(this: &EvalCtx, map: &mut OrderedMap, node: &AstNode) -> Result<(), Val> {
@@ -510,7 +850,14 @@ impl EvalCtx {
                     }
                 };
 
-                self.with_val(self.eval_fcn(name.as_str(), &args_vec))
+                let ctx = match self.with_call_frame(node) {
+                    Some(ctx) => ctx,
+                    None => return self.with_val(Val::new_err("maximum recursion depth exceeded")),
+                };
+                match self.functions.get(name) {
+                    Some(fcn) => self.with_val(ctx.eval_user_fcn(name.as_str(), fcn, &args_vec)),
+                    None => self.with_val(ctx.eval_fcn(name.as_str(), &args_vec)),
+                }
             }
             AstNodeType::LetStmt { identifier, expr } => {
                 let identifier = match identifier.get_type() {
@@ -525,9 +872,54 @@ impl EvalCtx {
 
                 EvalCtx {
                     variables,
-                    val: self.val.clone(),
+                    ..self.clone()
+                }
+            }
+            AstNodeType::FcnDef { name, params, body } => {
+                let name = match name.get_type() {
+                    AstNodeType::Identifier(name) => name.clone(),
+                    _ => panic!(),
+                };
+
+                let mut param_vec = Vec::<(String, Option<AstNode>)>::new();
+                fn helper(params: &mut Vec<(String, Option<AstNode>)>, node: &AstNode) {
+                    match node.get_type() {
+                        AstNodeType::ListNode(left, right) => {
+                            helper(params, left);
+                            helper(params, right);
+                        }
+                        AstNodeType::KeywordArgument(keyword, default) => {
+                            if let AstNodeType::Identifier(keyword) = keyword.get_type() {
+                                params.push((keyword.clone(), Some(default.clone())));
+                            }
+                        }
+                        AstNodeType::Identifier(param) => {
+                            params.push((param.clone(), None));
+                        }
+                        _ => panic!(),
+                    }
+                }
+                if let Some(params) = params {
+                    helper(&mut param_vec, params);
+                }
+
+                let fcn = UserFcn {
+                    name: name.clone(),
+                    params: param_vec,
+                    body: body.clone(),
+                    variables: self.variables.clone(),
+                    functions: self.functions.clone(),
+                    module: self.module.clone(),
+                };
+                let mut functions = self.functions.clone();
+                functions.insert(name, Rc::new(fcn));
+
+                EvalCtx {
+                    functions,
+                    ..self.clone()
                 }
             }
+            AstNodeType::Import { path, alias } => self.eval_import(path, alias.as_ref()),
             AstNodeType::Identifier(identifier) => match self.variables.get(identifier) {
                 None => self.with_val(Val::new_err("undefined variable access")),
                 Some(val) => self.with_val(val.clone()),
@@ -559,6 +951,107 @@ impl EvalCtx {
         }
     }
 
+    /// Calls a function defined with `def`. Arguments are evaluated against the
+    /// caller's input and bound as variables in the body, which is evaluated
+    /// with the caller's input as `.`.
+    fn eval_user_fcn(&self, name: &str, fcn: &Rc<UserFcn>, args: &Vec<AstNode>) -> Val {
+        let mut arg_vals: Vec<Option<Val>> = vec![None; fcn.params.len()];
+        let mut positional_idx = 0;
+        for arg in args {
+            match arg.get_type() {
+                AstNodeType::KeywordArgument(keyword, expr) => {
+                    let keyword = match keyword.get_type() {
+                        AstNodeType::Identifier(keyword) => keyword,
+                        _ => panic!(),
+                    };
+                    match fcn.params.iter().position(|(param, _)| param == keyword) {
+                        None => {
+                            return Val::new_err(
+                                format!(
+                                    "Unknown keyword argument :{} passed to {}()",
+                                    keyword, name
+                                )
+                                .as_str(),
+                            );
+                        }
+                        Some(param_idx) => {
+                            arg_vals[param_idx] = Some(self.eval(expr).val);
+                        }
+                    }
+                }
+                _ => {
+                    while positional_idx < arg_vals.len() && arg_vals[positional_idx].is_some() {
+                        positional_idx += 1;
+                    }
+                    if positional_idx >= arg_vals.len() {
+                        return Val::new_err(
+                            format!(
+                                "{}() must be called with at most {} arguments",
+                                name,
+                                fcn.params.len()
+                            )
+                            .as_str(),
+                        );
+                    }
+                    arg_vals[positional_idx] = Some(self.eval(arg).val);
+                }
+            }
+        }
+
+        let mut variables = fcn.variables.clone();
+        for ((param, default), arg_val) in fcn.params.iter().zip(arg_vals) {
+            let arg_val = match (arg_val, default) {
+                (Some(arg_val), _) => arg_val,
+                (None, Some(default)) => {
+                    EvalCtx {
+                        variables: variables.clone(),
+                        functions: fcn.functions.clone(),
+                        module: fcn.module.clone(),
+                        ..self.clone()
+                    }
+                    .eval(default)
+                    .val
+                }
+                (None, None) => {
+                    return Val::new_err(
+                        format!("{}() is missing the argument {}", name, param).as_str(),
+                    );
+                }
+            };
+            variables.insert(param.clone(), arg_val);
+        }
+
+        let mut functions = fcn.functions.clone();
+        functions.insert(fcn.name.clone(), fcn.clone());
+
+        let result = EvalCtx {
+            variables,
+            functions,
+            module: fcn.module.clone(),
+            ..self.clone()
+        }
+        .eval(&fcn.body)
+        .val;
+
+        // Spans of errors from another file don't point into the caller's
+        // text, so let the caller re-anchor them to the call site.
+        let same_module = match (&fcn.module, &self.module) {
+            (Some(left), Some(right)) => Rc::ptr_eq(left, right),
+            (None, None) => true,
+            _ => false,
+        };
+        match result.get_val() {
+            ValType::Err(err) if !same_module && err.span.is_some() => {
+                Val::new(ValType::Err(ErrVal {
+                    msg: err.msg.clone(),
+                    span: None,
+                    trace: err.trace.clone(),
+                }))
+            }
+            _ => result,
+        }
+    }
+
     fn eval_list_access_range(
         &self,
         accessor: &AstNode,
@@ -620,6 +1113,10 @@ impl EvalCtx {
         };
         let idx = self.eval(idx_expr).val;
         match idx.get_val() {
+            ValType::Int(num) if *num >= 0 => Ok((*num as usize, is_rev)),
+            ValType::Decimal(num) if num.is_integer() && num.to_usize().is_some() => {
+                Ok((num.to_usize().unwrap(), is_rev))
+            }
             ValType::Float64(num) => {
                 let num = *num;
                 if num == num.trunc() && num >= 0.0 {
@@ -649,6 +1146,188 @@ impl EvalCtx {
     }
 }
 
+/// Applies an arithmetic operator to two numbers, or returns None if either
+/// isn't a number. Integers stay integers unless `int_op` gives up, in which
+/// case the result is a float, or a decimal in decimal mode. Decimals stay
+/// exact unless a float is involved or `decimal_op` gives up.
+fn num_op(
+    left: &Val,
+    right: &Val,
+    decimal: bool,
+    int_op: impl Fn(i64, i64) -> Option<i64>,
+    decimal_op: impl Fn(&BigDecimal, &BigDecimal) -> Option<BigDecimal>,
+    float_op: impl Fn(f64, f64) -> f64,
+) -> Option<Val> {
+    let float_result = || Some(Val::new_f64(float_op(left.as_f64()?, right.as_f64()?)));
+    match (left.get_val(), right.get_val()) {
+        (ValType::Int(int_left), ValType::Int(int_right)) => {
+            if let Some(result) = int_op(*int_left, *int_right) {
+                return Some(Val::new_int(result));
+            }
+            if !decimal {
+                return float_result();
+            }
+        }
+        (ValType::Float64(_), _) | (_, ValType::Float64(_)) => return float_result(),
+        _ => {}
+    }
+    match decimal_op(&left.as_decimal()?, &right.as_decimal()?) {
+        Some(result) => Some(Val::new_decimal(result)),
+        None => float_result(),
+    }
+}
+
+/// Adds or subtracts seconds from a datetime, or subtracts two datetimes to
+/// get the seconds between them. Returns None when neither side is a datetime.
+fn datetime_op(left: &Val, right: &Val, op: ArithOp) -> Option<Val> {
+    let (datetime, zone, seconds, subtract) = match (left.get_val(), right.get_val(), op) {
+        (ValType::Datetime(left, _), ValType::Datetime(right, _), ArithOp::Subtract) => {
+            let delta = *left - *right;
+            return Some(match delta.subsec_nanos() {
+                0 => Val::new_int(delta.num_seconds()),
+                nanos => Val::new_f64(delta.num_seconds() as f64 + nanos as f64 / 1e9),
+            });
+        }
+        (ValType::Datetime(datetime, zone), _, ArithOp::Add) => (datetime, zone, right, false),
+        (ValType::Datetime(datetime, zone), _, ArithOp::Subtract) => (datetime, zone, right, true),
+        (_, ValType::Datetime(datetime, zone), ArithOp::Add) => (datetime, zone, left, false),
+        (ValType::Datetime(..), _, _) | (_, ValType::Datetime(..), _) => {
+            return Some(Val::new_err(
+                format!("{} isn't supported for datetimes", op.name()).as_str(),
+            ))
+        }
+        _ => return None,
+    };
+    let delta = match seconds_to_delta(seconds) {
+        Some(delta) => delta,
+        None => {
+            return Some(Val::new_err(
+                "Only a number of seconds can be added to or subtracted from a datetime",
+            ))
+        }
+    };
+    let result = if subtract {
+        datetime.checked_sub_signed(delta)
+    } else {
+        datetime.checked_add_signed(delta)
+    };
+    Some(match result {
+        Some(result) => Val::new_zoned_datetime(result, *zone),
+        None => Val::new_err("datetime out of range"),
+    })
+}
+
+/// Converts a number of seconds to a duration, to the nearest nanosecond.
+fn seconds_to_delta(seconds: &Val) -> Option<TimeDelta> {
+    match seconds.get_val() {
+        ValType::Int(seconds) => TimeDelta::try_seconds(*seconds),
+        _ => {
+            let nanos = (seconds.as_f64()? * 1e9).round();
+            if nanos.is_finite() && nanos.abs() < i64::MAX as f64 {
+                Some(TimeDelta::nanoseconds(nanos as i64))
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// The error for an arithmetic operator when either side isn't a number.
+fn num_operands_err(left: &Val, right: &Val, op_name: &str) -> Option<Val> {
+    let side = if left.as_f64().is_none() {
+        "Left"
+    } else if right.as_f64().is_none() {
+        "Right"
+    } else {
+        return None;
+    };
+    Some(Val::new_err(
+        format!("{} side of {} has to be a number", side, op_name).as_str(),
+    ))
+}
+
+/// Raises one number to the power of another for `**` and `pow()`. Both
+/// have to be numbers.
+fn num_pow(base: &Val, exp: &Val, decimal: bool) -> Val {
+    if base.as_f64() == Some(0.0) && exp.as_f64().unwrap() < 0.0 {
+        return Val::new_err("divide by zero");
+    }
+    num_op(
+        base,
+        exp,
+        decimal,
+        |base, exp| base.checked_pow(u32::try_from(exp).ok()?),
+        decimal_pow,
+        f64::powf,
+    )
+    .unwrap()
+}
+
+/// Most digits an exact decimal power may have before `**` falls back to
+/// floats.
+const MAX_DECIMAL_POW_DIGITS: u64 = 10_000;
+
+/// Raises a decimal to an integer power exactly, or returns None for other
+/// exponents and for results too long to compute quickly, so the caller can
+/// fall back to floats.
+fn decimal_pow(base: &BigDecimal, exp: &BigDecimal) -> Option<BigDecimal> {
+    if !exp.is_integer() {
+        return None;
+    }
+    let exp = exp.to_i64()?;
+    if base.digits().saturating_mul(exp.unsigned_abs()) > MAX_DECIMAL_POW_DIGITS {
+        return None;
+    }
+    let mut remaining = exp.unsigned_abs();
+    let mut square = base.clone();
+    let mut result = BigDecimal::one();
+    while remaining > 0 {
+        if remaining & 1 == 1 {
+            result *= &square;
+        }
+        remaining >>= 1;
+        if remaining > 0 {
+            square = square.square();
+        }
+    }
+    if exp < 0 {
+        Some(BigDecimal::one() / result)
+    } else {
+        Some(result)
+    }
+}
+
+/// Number of elements in a list or map, or bytes in a string, for the size
+/// limit on previews.
+fn val_len(val: &Val) -> usize {
+    match val.get_val() {
+        ValType::List(val) => val.len(),
+        ValType::Map(val) => val.len(),
+        ValType::String(val) => val.len(),
+        ValType::Bytes(val) => val.len(),
+        _ => 0,
+    }
+}
+
+/// The operators evaluated with `eval_num_expr()`.
+#[derive(Clone, Copy)]
+enum ArithOp {
+    Add,
+    Subtract,
+    Multiply,
+}
+
+impl ArithOp {
+    /// Name of the operator for error messages.
+    fn name(self) -> &'static str {
+        match self {
+            ArithOp::Add => "addition",
+            ArithOp::Subtract => "subtraction",
+            ArithOp::Multiply => "multiplication",
+        }
+    }
+}
+
 enum ListAccessRange {
     SingleValue(usize),
     Slice(usize, usize),
@@ -1,9 +1,17 @@
 mod ast_node;
+mod decimal_json;
+mod diagnostic;
 mod evaluator;
 mod parser;
+mod repl_commands;
+mod repl_helper;
+mod run_cli;
 mod run_repl;
+mod user_paths;
 mod val;
 
 // External exports
 pub use evaluator::EvalCtx;
+pub use run_cli::run_cli;
 pub use run_repl::run_repl;
+pub use val::Val;
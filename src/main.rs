@@ -1,12 +1,15 @@
-use kilojoule::{run_repl, EvalCtx};
+use kilojoule::run_cli;
+
+/// Evaluation recurses for every nested call, and debug builds use far more
+/// stack per call than the main thread's default allows for the deepest
+/// nesting the evaluator accepts.
+const STACK_SIZE: usize = 256 * 1024 * 1024;
 
 fn main() {
-    let mut args = std::env::args();
-    if args.len() != 2 {
-        let _ = run_repl();
-        return;
-    }
-    let query = args.nth(1).unwrap();
-    let result = EvalCtx::new().parse_and_eval(query.as_str()).val;
-    let _ = EvalCtx::write_val(&result, &mut std::io::stdout(), true);
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let cli = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || run_cli(args.as_slice()))
+        .unwrap();
+    std::process::exit(cli.join().unwrap_or(101));
 }
@@ -1,4 +1,5 @@
-use super::ast_node::{AstNode, AstNodeType};
+use super::ast_node::{AstNode, AstNodeType, Span};
+use super::diagnostic::{line_col, render_snippet};
 
 pub struct Parser<'a> {
     text: &'a str,
@@ -76,8 +77,9 @@ impl<'a> Parser<'a> {
             return None;
         }
 
+        let start_idx = self.idx;
         self.idx += idx;
-        Some(AstNode::new(AstNodeType::Identifier(iden_str.to_string())))
+        Some(self.new_node(start_idx, AstNodeType::Identifier(iden_str.to_string())))
     }
 
     fn parse_ws(&mut self) {
@@ -111,22 +113,13 @@ impl<'a> Parser<'a> {
         true
     }
 
-    fn parse_integer(&mut self) -> Option<u64> {
+    /// Parses a run of digits and returns them as written, so that leading
+    /// zeros are kept and long numbers don't overflow.
+    fn parse_integer(&mut self) -> Option<String> {
         let mut idx = 0 as usize;
-        let mut val = 0 as u64;
-        loop {
-            match self.peek(idx) {
-                None => {
-                    break;
-                }
-                Some(ch) => {
-                    if Parser::is_numeric(ch) {
-                        val *= 10;
-                        val += (ch - ('0' as u8)) as u64;
-                    } else {
-                        break;
-                    }
-                }
+        while let Some(ch) = self.peek(idx) {
+            if !Parser::is_numeric(ch) {
+                break;
             }
             idx += 1;
         }
@@ -134,16 +127,19 @@ impl<'a> Parser<'a> {
         if idx == 0 {
             return None;
         }
+        let digits = self.text[self.idx..self.idx + idx].to_string();
         self.idx += idx;
 
-        Some(val)
+        Some(digits)
     }
 
     fn parse_number(&mut self) -> Option<Result<AstNode, ParseError>> {
+        let start_idx = self.idx;
         let int_part = match self.parse_integer() {
             None => return None,
             Some(integer_part) => integer_part,
         };
+        let int_end_idx = self.idx;
 
         self.parse_ws();
         if self.parse_str_literal(".") {
@@ -158,19 +154,47 @@ impl<'a> Parser<'a> {
 
                     let float = serialized_number.parse::<f64>().unwrap();
 
-                    Some(Ok(AstNode::new(AstNodeType::Float64(float))))
+                    Some(Ok(self.new_node(
+                        start_idx,
+                        AstNodeType::Float64(float, serialized_number),
+                    )))
                 }
             }
         } else {
-            Some(Ok(AstNode::new(AstNodeType::Integer(int_part))))
+            // Integers too large for an i64 are evaluated from their text as decimals
+            let node_type = match int_part.parse::<i64>() {
+                Ok(int) => AstNodeType::Integer(int),
+                Err(_) => AstNodeType::Float64(int_part.parse::<f64>().unwrap(), int_part),
+            };
+            Some(Ok(AstNode::new_with_span(
+                node_type,
+                Span::new(start_idx, int_end_idx),
+            )))
         }
     }
 
     fn get_err(&self, typ: ParseErrorType) -> ParseError {
-        ParseError { idx: self.idx, typ }
+        ParseError {
+            idx: self.idx,
+            typ,
+            opened_at: None,
+            text: self.text.to_string(),
+        }
+    }
+
+    fn new_node(&self, start_idx: usize, node_type: AstNodeType) -> AstNode {
+        AstNode::new_with_span(node_type, Span::new(start_idx, self.idx))
+    }
+
+    fn get_err_opened_at(&self, typ: ParseErrorType, start: usize, end: usize) -> ParseError {
+        ParseError {
+            opened_at: Some((start, end)),
+            ..self.get_err(typ)
+        }
     }
 
     fn parse_list_literal(&mut self) -> Option<Result<AstNode, ParseError>> {
+        let start_idx = self.idx;
         if !self.parse_str_literal("[") {
             return None;
         }
@@ -180,6 +204,7 @@ impl<'a> Parser<'a> {
         loop {
             self.parse_ws();
 
+            let spread_start_idx = self.idx;
             let is_spread = if self.parse_str_literal("*") {
                 self.parse_ws();
                 true
@@ -199,14 +224,18 @@ impl<'a> Parser<'a> {
                 },
             };
             if is_spread {
-                elem = AstNode::new(AstNodeType::Spread(elem));
+                elem = self.new_node(spread_start_idx, AstNodeType::Spread(elem));
             }
             match parts {
                 None => {
                     parts = Some(elem);
                 }
                 Some(prev_val) => {
-                    parts = Some(AstNode::new(AstNodeType::ListNode(prev_val, elem)));
+                    parts = Some(AstNode::new_spanning(
+                        AstNodeType::ListNode(prev_val.clone(), elem.clone()),
+                        &prev_val,
+                        &elem,
+                    ));
                 }
             }
 
@@ -218,13 +247,18 @@ impl<'a> Parser<'a> {
         }
 
         if !self.parse_str_literal("]") {
-            return Some(Err(self.get_err(ParseErrorType::NoListLiteralEndingBracket)));
+            return Some(Err(self.get_err_opened_at(
+                ParseErrorType::NoListLiteralEndingBracket,
+                start_idx,
+                start_idx + 1,
+            )));
         }
 
-        Some(Ok(AstNode::new(AstNodeType::ListLiteral(parts))))
+        Some(Ok(self.new_node(start_idx, AstNodeType::ListLiteral(parts))))
     }
 
     fn parse_map_literal(&mut self) -> Option<Result<AstNode, ParseError>> {
+        let start_idx = self.idx;
         if !self.parse_str_literal("{") {
             return None;
         }
@@ -234,6 +268,7 @@ impl<'a> Parser<'a> {
         loop {
             self.parse_ws();
 
+            let part_start_idx = self.idx;
             if self.parse_str_literal("*") {
                 self.parse_ws();
                 let expr = match self.parse_expr() {
@@ -247,13 +282,17 @@ impl<'a> Parser<'a> {
                         Ok(expr) => expr,
                     },
                 };
-                let new_part = AstNode::new(AstNodeType::Spread(expr));
+                let new_part = self.new_node(part_start_idx, AstNodeType::Spread(expr));
                 match parts {
                     None => {
                         parts = Some(new_part);
                     }
                     Some(prev_val) => {
-                        parts = Some(AstNode::new(AstNodeType::ListNode(prev_val, new_part)));
+                        parts = Some(AstNode::new_spanning(
+                            AstNodeType::ListNode(prev_val.clone(), new_part.clone()),
+                            &prev_val,
+                            &new_part,
+                        ));
                     }
                 }
             } else if self.parse_str_literal("-") {
@@ -267,16 +306,21 @@ impl<'a> Parser<'a> {
                         Ok(expr) => expr,
                     },
                 };
-                let new_part = AstNode::new(AstNodeType::MapDelete(expr));
+                let new_part = self.new_node(part_start_idx, AstNodeType::MapDelete(expr));
                 match parts {
                     None => {
                         parts = Some(new_part);
                     }
                     Some(prev_val) => {
-                        parts = Some(AstNode::new(AstNodeType::ListNode(prev_val, new_part)));
+                        parts = Some(AstNode::new_spanning(
+                            AstNodeType::ListNode(prev_val.clone(), new_part.clone()),
+                            &prev_val,
+                            &new_part,
+                        ));
                     }
                 }
             } else {
+                let key_start_idx = self.idx;
                 let key = if self.parse_str_literal("[") {
                     self.parse_ws();
                     let key = match self.parse_expr() {
@@ -288,7 +332,11 @@ impl<'a> Parser<'a> {
                     };
                     self.parse_ws();
                     if !self.parse_str_literal("]") {
-                        return Some(Err(self.get_err(ParseErrorType::NoClosingBracketForMapKey)));
+                        return Some(Err(self.get_err_opened_at(
+                            ParseErrorType::NoClosingBracketForMapKey,
+                            key_start_idx,
+                            key_start_idx + 1,
+                        )));
                     }
                     key
                 } else if let Some(f_string) = self.parse_format_string() {
@@ -323,13 +371,18 @@ impl<'a> Parser<'a> {
                     },
                 };
 
-                let kv_pair = AstNode::new(AstNodeType::MapKeyValPair { key, val });
+                let kv_pair =
+                    self.new_node(part_start_idx, AstNodeType::MapKeyValPair { key, val });
                 match parts {
                     None => {
                         parts = Some(kv_pair);
                     }
                     Some(prev_val) => {
-                        parts = Some(AstNode::new(AstNodeType::ListNode(prev_val, kv_pair)));
+                        parts = Some(AstNode::new_spanning(
+                            AstNodeType::ListNode(prev_val.clone(), kv_pair.clone()),
+                            &prev_val,
+                            &kv_pair,
+                        ));
                     }
                 }
             }
@@ -342,20 +395,27 @@ impl<'a> Parser<'a> {
         }
 
         if !self.parse_str_literal("}") {
-            return Some(Err(self.get_err(ParseErrorType::NoMapLiteralEndingBrace)));
+            return Some(Err(self.get_err_opened_at(
+                ParseErrorType::NoMapLiteralEndingBrace,
+                start_idx,
+                start_idx + 1,
+            )));
         }
 
-        Some(Ok(AstNode::new(AstNodeType::MapLiteral(parts))))
+        Some(Ok(self.new_node(start_idx, AstNodeType::MapLiteral(parts))))
     }
 
     fn get_substring(&mut self, start_offset: usize, end_offset: usize) -> AstNode {
-        AstNode::new(AstNodeType::SubString(
-            std::str::from_utf8(
-                &self.text.as_bytes()[self.idx + start_offset..self.idx + end_offset],
-            )
-            .unwrap()
-            .to_string(),
-        ))
+        AstNode::new_with_span(
+            AstNodeType::SubString(
+                std::str::from_utf8(
+                    &self.text.as_bytes()[self.idx + start_offset..self.idx + end_offset],
+                )
+                .unwrap()
+                .to_string(),
+            ),
+            Span::new(self.idx + start_offset, self.idx + end_offset),
+        )
     }
 
     fn parse_format_string(&mut self) -> Option<Result<AstNode, ParseError>> {
@@ -374,6 +434,7 @@ impl<'a> Parser<'a> {
         if self.peek(0) != Some(quote_char) {
             return None;
         }
+        let start_idx = self.idx;
         self.idx += 1;
 
         let mut parts: Option<AstNode> = None;
@@ -382,7 +443,12 @@ impl<'a> Parser<'a> {
         loop {
             match self.peek(idx) {
                 None => {
-                    return Some(Err(self.get_err(ParseErrorType::NoClosingQuoteOnString)));
+                    self.idx = self.text.len();
+                    return Some(Err(self.get_err_opened_at(
+                        ParseErrorType::NoClosingQuoteOnString,
+                        start_idx,
+                        start_idx + 1,
+                    )));
                 }
                 Some(ch) => {
                     if ch == quote_char || ch == ('{' as u8) {
@@ -392,13 +458,18 @@ impl<'a> Parser<'a> {
                                 parts = Some(part);
                             }
                             Some(prev) => {
-                                parts = Some(AstNode::new(AstNodeType::ListNode(prev, part)));
+                                parts = Some(AstNode::new_spanning(
+                                    AstNodeType::ListNode(prev.clone(), part.clone()),
+                                    &prev,
+                                    &part,
+                                ));
                             }
                         }
 
                         if ch == quote_char {
                             break;
                         } else {
+                            let brace_idx = self.idx + idx;
                             self.idx += idx + 1;
                             self.parse_ws();
                             let part = match self.parse_expr() {
@@ -420,15 +491,21 @@ impl<'a> Parser<'a> {
                                     parts = Some(part);
                                 }
                                 Some(prev) => {
-                                    parts = Some(AstNode::new(AstNodeType::ListNode(prev, part)));
+                                    parts = Some(AstNode::new_spanning(
+                                        AstNodeType::ListNode(prev.clone(), part.clone()),
+                                        &prev,
+                                        &part,
+                                    ));
                                 }
                             }
 
                             self.parse_ws();
                             if !self.parse_str_literal("}") {
-                                return Some(Err(
-                                    self.get_err(ParseErrorType::NoClosingBraceInFormatString)
-                                ));
+                                return Some(Err(self.get_err_opened_at(
+                                    ParseErrorType::NoClosingBraceInFormatString,
+                                    brace_idx,
+                                    brace_idx + 1,
+                                )));
                             }
                             idx = 0;
                         }
@@ -443,7 +520,9 @@ impl<'a> Parser<'a> {
 
         self.idx += idx + 1;
 
-        Some(Ok(AstNode::new(AstNodeType::FormatString(parts))))
+        Some(Ok(
+            self.new_node(start_idx, AstNodeType::FormatString(parts))
+        ))
     }
 
     fn parse_base_expr_with_accesses(&mut self) -> Option<Result<AstNode, ParseError>> {
@@ -470,7 +549,11 @@ impl<'a> Parser<'a> {
                     Ok(expr) => expr,
                 },
             };
-            expr = AstNode::new(AstNodeType::AccessChain(expr, accessor));
+            expr = AstNode::new_spanning(
+                AstNodeType::AccessChain(expr.clone(), accessor.clone()),
+                &expr,
+                &accessor,
+            );
         }
 
         Some(Ok(expr))
@@ -485,11 +568,24 @@ impl<'a> Parser<'a> {
             },
         }
 
+        if let Some(expr) = self.parse_fcn_def() {
+            return Some(expr);
+        }
+
+        if let Some(expr) = self.parse_import() {
+            return Some(expr);
+        }
+
+        let start_idx = self.idx;
         if self.parse_str_literal(".") {
-            let mut expr = AstNode::new(AstNodeType::Dot);
+            let mut expr = self.new_node(start_idx, AstNodeType::Dot);
             self.parse_ws();
             if let Some(iden) = self.parse_identifier(true) {
-                expr = AstNode::new(AstNodeType::AccessChain(expr, iden));
+                expr = AstNode::new_spanning(
+                    AstNodeType::AccessChain(expr.clone(), iden.clone()),
+                    &expr,
+                    &iden,
+                );
             }
             return Some(Ok(expr));
         }
@@ -506,7 +602,11 @@ impl<'a> Parser<'a> {
             };
             self.parse_ws();
             if !self.parse_str_literal(")") {
-                return Some(Err(self.get_err(ParseErrorType::NoClosingParen)));
+                return Some(Err(self.get_err_opened_at(
+                    ParseErrorType::NoClosingParen,
+                    start_idx,
+                    start_idx + 1,
+                )));
             }
             return Some(Ok(expr));
         }
@@ -534,21 +634,44 @@ impl<'a> Parser<'a> {
         //     return Some(Ok(expr));
         // }
         if self.parse_str_literal("null") {
-            return Some(Ok(AstNode::new(AstNodeType::Null)));
+            return Some(Ok(self.new_node(start_idx, AstNodeType::Null)));
         }
         if self.parse_str_literal("true") {
-            return Some(Ok(AstNode::new(AstNodeType::Bool(true))));
+            return Some(Ok(self.new_node(start_idx, AstNodeType::Bool(true))));
         }
         if self.parse_str_literal("false") {
-            return Some(Ok(AstNode::new(AstNodeType::Bool(false))));
+            return Some(Ok(self.new_node(start_idx, AstNodeType::Bool(false))));
         }
-        if let Some(expr) = self.parse_identifier(true) {
+        let name_start_idx = self.idx;
+        if let Some(mut expr) = self.parse_identifier(true) {
+            // Functions from an imported module are called as `module::name`
+            while self.parse_str_literal("::") {
+                let namespace = match expr.get_type() {
+                    AstNodeType::Identifier(namespace) => namespace.clone(),
+                    _ => panic!(),
+                };
+                let name = match self.parse_identifier(false) {
+                    None => {
+                        return Some(Err(self.get_err(ParseErrorType::NoIdentifierAfterNamespace)));
+                    }
+                    Some(name) => match name.get_type() {
+                        AstNodeType::Identifier(name) => name.clone(),
+                        _ => panic!(),
+                    },
+                };
+                expr = self.new_node(
+                    name_start_idx,
+                    AstNodeType::Identifier(format!("{}::{}", namespace, name)),
+                );
+            }
             self.parse_ws();
             if self.parse_str_literal("(") {
+                let paren_end_idx = self.idx;
                 // This is a function call
                 self.parse_ws();
                 let mut args_node: Option<AstNode> = None;
                 loop {
+                    let arg_start_idx = self.idx;
                     let keyword = if self.parse_str_literal(":") {
                         self.parse_ws();
                         match self.parse_identifier(false) {
@@ -581,15 +704,18 @@ impl<'a> Parser<'a> {
                             Ok(expr) => {
                                 let expr = match keyword {
                                     None => expr,
-                                    Some(keyword) => {
-                                        AstNode::new(AstNodeType::KeywordArgument(keyword, expr))
-                                    }
+                                    Some(keyword) => self.new_node(
+                                        arg_start_idx,
+                                        AstNodeType::KeywordArgument(keyword, expr),
+                                    ),
                                 };
                                 args_node = Some(match args_node {
                                     None => expr,
-                                    Some(args_node) => {
-                                        AstNode::new(AstNodeType::ListNode(args_node, expr))
-                                    }
+                                    Some(args_node) => AstNode::new_spanning(
+                                        AstNodeType::ListNode(args_node.clone(), expr.clone()),
+                                        &args_node,
+                                        &expr,
+                                    ),
                                 })
                             }
                         },
@@ -603,12 +729,23 @@ impl<'a> Parser<'a> {
                 }
 
                 if self.parse_str_literal(")") {
-                    return Some(Ok(AstNode::new(AstNodeType::FcnCall {
-                        name: expr,
-                        args: args_node,
-                    })));
+                    return Some(Ok(self.new_node(
+                        name_start_idx,
+                        AstNodeType::FcnCall {
+                            name: expr,
+                            args: args_node,
+                        },
+                    )));
                 } else {
-                    return Some(Err(self.get_err(ParseErrorType::NoClosingParenFcnCall)));
+                    let name = match expr.get_type() {
+                        AstNodeType::Identifier(name) => name.clone(),
+                        _ => panic!(),
+                    };
+                    return Some(Err(self.get_err_opened_at(
+                        ParseErrorType::NoClosingParenFcnCall(name),
+                        name_start_idx,
+                        paren_end_idx,
+                    )));
                 }
             }
 
@@ -758,13 +895,14 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_access_expr(&mut self) -> Option<Result<AstNode, ParseError>> {
+        let start_idx = self.idx;
         if self.parse_str_literal("/") {
             self.parse_ws();
             match self.parse_expr() {
                 None => Some(Err(self.get_err(ParseErrorType::NoExprReverseIndex))),
                 Some(expr) => match expr {
                     Err(err) => Some(Err(err)),
-                    Ok(expr) => Some(Ok(AstNode::new(AstNodeType::ReverseIdx(expr)))),
+                    Ok(expr) => Some(Ok(self.new_node(start_idx, AstNodeType::ReverseIdx(expr)))),
                 },
             }
         } else {
@@ -784,6 +922,7 @@ impl<'a> Parser<'a> {
 
             Some(Ok(identifier))
         } else if self.parse_str_literal("[") {
+            let bracket_idx = self.idx - 1;
             self.parse_ws();
 
             let start_expr = match self.parse_access_expr() {
@@ -810,7 +949,10 @@ impl<'a> Parser<'a> {
                     },
                 };
                 self.parse_ws();
-                AstNode::new(AstNodeType::SliceAccess(start_expr, end_expr))
+                AstNode::new_with_span(
+                    AstNodeType::SliceAccess(start_expr, end_expr),
+                    Span::new(bracket_idx, self.idx + 1),
+                )
             } else {
                 match start_expr {
                     None => {
@@ -823,9 +965,11 @@ impl<'a> Parser<'a> {
             };
 
             if !self.parse_str_literal("]") {
-                return Some(Err(
-                    self.get_err(ParseErrorType::NoClosingBracketForBracketAccess)
-                ));
+                return Some(Err(self.get_err_opened_at(
+                    ParseErrorType::NoClosingBracketForBracketAccess,
+                    bracket_idx,
+                    bracket_idx + 1,
+                )));
             }
 
             Some(Ok(access_expr))
@@ -837,7 +981,7 @@ impl<'a> Parser<'a> {
     pub fn external_parse_expr(&mut self) -> Option<Result<AstNode, ParseError>> {
         self.parse_ws();
         let expr = match self.parse_expr() {
-            None => AstNode::new(AstNodeType::Null),
+            None => self.new_node(self.idx, AstNodeType::Null),
             Some(expr) => match expr {
                 Err(err) => {
                     return Some(Err(err));
@@ -886,6 +1030,9 @@ impl<'a> Parser<'a> {
             Subtract,
             Multiply,
             Divide,
+            Modulo,
+            FloorDivide,
+            Power,
         }
 
         #[derive(PartialEq, PartialOrd, Clone, Copy)]
@@ -900,11 +1047,12 @@ impl<'a> Parser<'a> {
             Add,
             Multiply,
             Negative,
+            Power,
         }
 
         enum Node {
             Expr(AstNode),
-            Op(Op, OpOrder),
+            Op(Op, OpOrder, usize),
         }
 
         let mut stack = Vec::<Node>::new();
@@ -913,17 +1061,26 @@ impl<'a> Parser<'a> {
             // normal reduction
             // [a | b + c] |
             while stack.len() > 1 {
-                if let (Node::Op(temp_op, temp_order), Node::Expr(right)) =
+                if let (Node::Op(temp_op, temp_order, op_start_idx), Node::Expr(right)) =
                     (&stack[stack.len() - 2], &stack[stack.len() - 1])
                 {
                     let temp_order = *temp_order;
+                    let op_start_idx = *op_start_idx;
                     let right = right.clone();
-                    if temp_order >= order {
+                    // `**` is right associative, so 2 ** 3 ** 2 is 2 ** 9
+                    if temp_order > order || (temp_order == order && order != OpOrder::Power) {
                         match temp_op {
                             Op::Unary(temp_op) => {
                                 let new_expr = match temp_op {
-                                    UnaryOp::Not => AstNode::new(AstNodeType::Not(right)),
-                                    UnaryOp::Negative => AstNode::new(AstNodeType::Negative(right)),
+                                    UnaryOp::Not => AstNodeType::Not(right.clone()),
+                                    UnaryOp::Negative => AstNodeType::Negative(right.clone()),
+                                };
+                                let new_expr = match right.get_span() {
+                                    None => AstNode::new(new_expr),
+                                    Some(span) => AstNode::new_with_span(
+                                        new_expr,
+                                        Span::new(op_start_idx, span.end),
+                                    ),
                                 };
                                 stack.pop();
                                 stack.pop();
@@ -932,48 +1089,35 @@ impl<'a> Parser<'a> {
                             Op::Binary(temp_op) => {
                                 if let Node::Expr(left) = &stack[stack.len() - 3] {
                                     let left = left.clone();
+                                    let (first, last) = (left.clone(), right.clone());
                                     let new_expr = match temp_op {
-                                        BinaryOp::Pipe => {
-                                            AstNode::new(AstNodeType::Pipe(left, right))
-                                        }
-                                        BinaryOp::Coalesce => {
-                                            AstNode::new(AstNodeType::Coalesce(left, right))
-                                        }
-                                        BinaryOp::Or => AstNode::new(AstNodeType::Or(left, right)),
-                                        BinaryOp::And => {
-                                            AstNode::new(AstNodeType::And(left, right))
-                                        }
-                                        BinaryOp::Equals => {
-                                            AstNode::new(AstNodeType::Equals(left, right))
-                                        }
-                                        BinaryOp::NotEquals => {
-                                            AstNode::new(AstNodeType::NotEquals(left, right))
-                                        }
-                                        BinaryOp::LessThan => {
-                                            AstNode::new(AstNodeType::LessThan(left, right))
-                                        }
+                                        BinaryOp::Pipe => AstNodeType::Pipe(left, right),
+                                        BinaryOp::Coalesce => AstNodeType::Coalesce(left, right),
+                                        BinaryOp::Or => AstNodeType::Or(left, right),
+                                        BinaryOp::And => AstNodeType::And(left, right),
+                                        BinaryOp::Equals => AstNodeType::Equals(left, right),
+                                        BinaryOp::NotEquals => AstNodeType::NotEquals(left, right),
+                                        BinaryOp::LessThan => AstNodeType::LessThan(left, right),
                                         BinaryOp::LessThanOrEqual => {
-                                            AstNode::new(AstNodeType::LessThanOrEqual(left, right))
+                                            AstNodeType::LessThanOrEqual(left, right)
                                         }
                                         BinaryOp::GreaterThan => {
-                                            AstNode::new(AstNodeType::GreaterThan(left, right))
-                                        }
-                                        BinaryOp::GreaterThanOrEqual => AstNode::new(
-                                            AstNodeType::GreaterThanOrEqual(left, right),
-                                        ),
-                                        BinaryOp::Add => {
-                                            AstNode::new(AstNodeType::Add(left, right))
-                                        }
-                                        BinaryOp::Subtract => {
-                                            AstNode::new(AstNodeType::Subtract(left, right))
+                                            AstNodeType::GreaterThan(left, right)
                                         }
-                                        BinaryOp::Multiply => {
-                                            AstNode::new(AstNodeType::Multiply(left, right))
+                                        BinaryOp::GreaterThanOrEqual => {
+                                            AstNodeType::GreaterThanOrEqual(left, right)
                                         }
-                                        BinaryOp::Divide => {
-                                            AstNode::new(AstNodeType::Divide(left, right))
+                                        BinaryOp::Add => AstNodeType::Add(left, right),
+                                        BinaryOp::Subtract => AstNodeType::Subtract(left, right),
+                                        BinaryOp::Multiply => AstNodeType::Multiply(left, right),
+                                        BinaryOp::Divide => AstNodeType::Divide(left, right),
+                                        BinaryOp::Modulo => AstNodeType::Modulo(left, right),
+                                        BinaryOp::FloorDivide => {
+                                            AstNodeType::FloorDivide(left, right)
                                         }
+                                        BinaryOp::Power => AstNodeType::Power(left, right),
                                     };
+                                    let new_expr = AstNode::new_spanning(new_expr, &first, &last);
                                     stack.pop();
                                     stack.pop();
                                     stack.pop();
@@ -999,13 +1143,22 @@ impl<'a> Parser<'a> {
             let mut has_unary_op = false;
 
             loop {
+                let op_start_idx = parser.idx;
                 if parser.parse_str_literal("not") {
                     parser.parse_ws();
-                    stack.push(Node::Op(Op::Unary(UnaryOp::Not), OpOrder::Not));
+                    stack.push(Node::Op(
+                        Op::Unary(UnaryOp::Not),
+                        OpOrder::Not,
+                        op_start_idx,
+                    ));
                     has_unary_op = true;
                 } else if parser.parse_str_literal("-") {
                     parser.parse_ws();
-                    stack.push(Node::Op(Op::Unary(UnaryOp::Negative), OpOrder::Negative));
+                    stack.push(Node::Op(
+                        Op::Unary(UnaryOp::Negative),
+                        OpOrder::Negative,
+                        op_start_idx,
+                    ));
                     has_unary_op = true;
                 } else {
                     break;
@@ -1044,6 +1197,7 @@ impl<'a> Parser<'a> {
 
         loop {
             self.parse_ws();
+            let op_start_idx = self.idx;
             if let Some((next_op, next_order)) = if allow_pipe && self.parse_str_literal("|") {
                 Some((BinaryOp::Pipe, OpOrder::Pipe))
             } else if self.parse_str_literal("??") {
@@ -1068,17 +1222,23 @@ impl<'a> Parser<'a> {
                 Some((BinaryOp::Add, OpOrder::Add))
             } else if self.parse_str_literal("-") {
                 Some((BinaryOp::Subtract, OpOrder::Add))
+            } else if self.parse_str_literal("**") {
+                Some((BinaryOp::Power, OpOrder::Power))
             } else if self.parse_str_literal("*") {
                 Some((BinaryOp::Multiply, OpOrder::Multiply))
+            } else if self.parse_str_literal("//") {
+                Some((BinaryOp::FloorDivide, OpOrder::Multiply))
             } else if self.parse_str_literal("/") {
                 Some((BinaryOp::Divide, OpOrder::Multiply))
+            } else if self.parse_str_literal("%") {
+                Some((BinaryOp::Modulo, OpOrder::Multiply))
             } else {
                 break;
             } {
                 self.parse_ws();
 
                 reduce_for_op_order(&mut stack, next_order);
-                stack.push(Node::Op(Op::Binary(next_op), next_order));
+                stack.push(Node::Op(Op::Binary(next_op), next_order, op_start_idx));
 
                 match parse_base_expr(&mut stack, self) {
                     None => {
@@ -1106,6 +1266,7 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_let_expr(&mut self) -> Option<Result<AstNode, ParseError>> {
+        let start_idx = self.idx;
         if !self.parse_str_literal("let") {
             return None;
         }
@@ -1130,7 +1291,320 @@ impl<'a> Parser<'a> {
             },
         };
 
-        Some(Ok(AstNode::new(AstNodeType::LetStmt { identifier, expr })))
+        Some(Ok(self.new_node(
+            start_idx,
+            AstNodeType::LetStmt { identifier, expr },
+        )))
+    }
+
+    /// Parses `import "path" as name; rest` or `include "path"; rest`. Like
+    /// `def`, the import is piped into `rest`.
+    fn parse_import(&mut self) -> Option<Result<AstNode, ParseError>> {
+        let start_idx = self.idx;
+        let is_include = if self.parse_str_literal("import") {
+            false
+        } else if self.parse_str_literal("include") {
+            true
+        } else {
+            return None;
+        };
+        self.parse_ws();
+
+        let path = match self.parse_format_string() {
+            None => {
+                self.idx = start_idx;
+                return None;
+            }
+            Some(Err(err)) => return Some(Err(err)),
+            Some(Ok(path)) => path,
+        };
+        self.parse_ws();
+
+        let alias = if is_include {
+            None
+        } else {
+            if !self.parse_str_literal("as") || !self.parse_at_least_one_ws() {
+                return Some(Err(self.get_err(ParseErrorType::NoAliasInImport)));
+            }
+            match self.parse_identifier(true) {
+                None => return Some(Err(self.get_err(ParseErrorType::NoAliasInImport))),
+                Some(alias) => Some(alias),
+            }
+        };
+        self.parse_ws();
+
+        if !self.parse_str_literal(";") {
+            return Some(Err(self.get_err(ParseErrorType::NoSemicolonAfterImport)));
+        }
+        let import = self.new_node(start_idx, AstNodeType::Import { path, alias });
+        self.parse_ws();
+
+        match self.parse_expr() {
+            None => Some(Ok(import)),
+            Some(Err(err)) => Some(Err(err)),
+            Some(Ok(rest)) => Some(Ok(AstNode::new_spanning(
+                AstNodeType::Pipe(import.clone(), rest.clone()),
+                &import,
+                &rest,
+            ))),
+        }
+    }
+
+    /// Parses `def name(param, :keyword default) = body; rest`. The definition
+    /// is piped into `rest` so that the function is in scope for it.
+    fn parse_fcn_def(&mut self) -> Option<Result<AstNode, ParseError>> {
+        let start_idx = self.idx;
+        if !self.parse_str_literal("def") {
+            return None;
+        }
+        if !self.parse_at_least_one_ws() {
+            self.idx = start_idx;
+            return None;
+        }
+
+        // Without a name and `(` this is a variable or function called def
+        let name = match self.parse_identifier(true) {
+            None => {
+                self.idx = start_idx;
+                return None;
+            }
+            Some(name) => name,
+        };
+        self.parse_ws();
+
+        let paren_idx = self.idx;
+        if !self.parse_str_literal("(") {
+            self.idx = start_idx;
+            return None;
+        }
+        self.parse_ws();
+
+        let mut params: Option<AstNode> = None;
+        loop {
+            let param_start_idx = self.idx;
+            let param = if self.parse_str_literal(":") {
+                self.parse_ws();
+                let keyword = match self.parse_identifier(false) {
+                    None => {
+                        return Some(Err(
+                            self.get_err(ParseErrorType::NoIdentifierAfterKeywordArgument)
+                        ));
+                    }
+                    Some(keyword) => keyword,
+                };
+                if !self.parse_at_least_one_ws() {
+                    return Some(Err(
+                        self.get_err(ParseErrorType::NoWhitespaceAfterKeywordArgumentKeyword)
+                    ));
+                }
+                let default = match self.parse_expr() {
+                    None => return Some(Err(self.get_err(ParseErrorType::NoExprInFcnDef))),
+                    Some(Err(err)) => return Some(Err(err)),
+                    Some(Ok(default)) => default,
+                };
+                self.new_node(
+                    param_start_idx,
+                    AstNodeType::KeywordArgument(keyword, default),
+                )
+            } else {
+                match self.parse_identifier(true) {
+                    None => break,
+                    Some(param) => param,
+                }
+            };
+
+            params = Some(match params {
+                None => param,
+                Some(params) => AstNode::new_spanning(
+                    AstNodeType::ListNode(params.clone(), param.clone()),
+                    &params,
+                    &param,
+                ),
+            });
+
+            self.parse_ws();
+            if !self.parse_str_literal(",") {
+                break;
+            }
+            self.parse_ws();
+        }
+
+        self.parse_ws();
+        if !self.parse_str_literal(")") {
+            return Some(Err(self.get_err_opened_at(
+                ParseErrorType::NoClosingParenInFcnDef,
+                paren_idx,
+                paren_idx + 1,
+            )));
+        }
+        self.parse_ws();
+
+        if !self.parse_str_literal("=") {
+            return Some(Err(self.get_err(ParseErrorType::NoEqualsInFcnDef)));
+        }
+        self.parse_ws();
+
+        let body = match self.parse_expr() {
+            None => return Some(Err(self.get_err(ParseErrorType::NoExprInFcnDef))),
+            Some(Err(err)) => return Some(Err(err)),
+            Some(Ok(body)) => body,
+        };
+        self.parse_ws();
+
+        if !self.parse_str_literal(";") {
+            return Some(Err(self.get_err(ParseErrorType::NoSemicolonAfterFcnDef)));
+        }
+        let fcn_def = self.new_node(start_idx, AstNodeType::FcnDef { name, params, body });
+        self.parse_ws();
+
+        match self.parse_expr() {
+            None => Some(Ok(fcn_def)),
+            Some(Err(err)) => Some(Err(err)),
+            Some(Ok(rest)) => Some(Ok(AstNode::new_spanning(
+                AstNodeType::Pipe(fcn_def.clone(), rest.clone()),
+                &fcn_def,
+                &rest,
+            ))),
+        }
+    }
+
+    /// Splits the text into the tokens that the REPL highlights. Unlike
+    /// parsing this never fails, so incomplete input is still tokenized.
+    pub fn tokenize(&mut self) -> Vec<Token> {
+        let mut tokens = Vec::<Token>::new();
+        // Quote and bracket depth of the strings whose `{...}` we're inside
+        let mut interpolations = Vec::<(u8, usize)>::new();
+        let mut depth: usize = 0;
+        loop {
+            self.parse_ws();
+            let start_idx = self.idx;
+            let ch = match self.peek(0) {
+                None => break,
+                Some(ch) => ch,
+            };
+
+            if ch == b'"' || ch == b'\'' {
+                self.idx += 1;
+                self.tokenize_str(ch, start_idx, depth, &mut tokens, &mut interpolations);
+            } else if ch == b'}' && interpolations.last().map(|(_, at)| *at) == Some(depth) {
+                let (quote_char, _) = interpolations.pop().unwrap();
+                self.idx += 1;
+                self.tokenize_str(
+                    quote_char,
+                    start_idx,
+                    depth,
+                    &mut tokens,
+                    &mut interpolations,
+                );
+            } else if b"([{".contains(&ch) {
+                self.idx += 1;
+                depth += 1;
+                tokens.push(Token::new(TokenType::OpenBracket, start_idx, self.idx));
+            } else if b")]}".contains(&ch) {
+                self.idx += 1;
+                depth = depth.saturating_sub(1);
+                tokens.push(Token::new(TokenType::CloseBracket, start_idx, self.idx));
+            } else if Parser::is_numeric(ch) {
+                self.parse_integer();
+                if self.peek(0) == Some(b'.') && self.peek(1).is_some_and(Parser::is_numeric) {
+                    self.idx += 1;
+                    self.parse_integer();
+                }
+                tokens.push(Token::new(TokenType::Number, start_idx, self.idx));
+            } else if let Some(identifier) = self.parse_identifier(false) {
+                let is_keyword = match identifier.get_type() {
+                    AstNodeType::Identifier(name) => KEYWORDS.contains(&name.as_str()),
+                    _ => false,
+                };
+                // Namespaced calls like `k8s::names()` are a single name
+                while self.peek(0) == Some(b':')
+                    && self.peek(1) == Some(b':')
+                    && self.peek(2).is_some_and(Parser::is_alpha_underscore)
+                {
+                    self.idx += 2;
+                    self.parse_identifier(false);
+                }
+                let name_end_idx = self.idx;
+                self.parse_ws();
+                let typ = if is_keyword {
+                    TokenType::Keyword
+                } else if self.peek(0) == Some(b'(') {
+                    TokenType::FcnName
+                } else {
+                    TokenType::Identifier
+                };
+                tokens.push(Token::new(typ, start_idx, name_end_idx));
+            } else {
+                self.idx += 1;
+            }
+        }
+        tokens
+    }
+
+    /// Tokenizes the rest of a string up to its closing quote or the start of
+    /// a `{...}` interpolation.
+    fn tokenize_str(
+        &mut self,
+        quote_char: u8,
+        start_idx: usize,
+        depth: usize,
+        tokens: &mut Vec<Token>,
+        interpolations: &mut Vec<(u8, usize)>,
+    ) {
+        loop {
+            match self.peek(0) {
+                None => break,
+                Some(ch) if ch == quote_char => {
+                    self.idx += 1;
+                    break;
+                }
+                Some(b'{') => {
+                    self.idx += 1;
+                    interpolations.push((quote_char, depth));
+                    break;
+                }
+                Some(b'\\') => {
+                    self.idx += 1;
+                    if let Some(escaped) = self.text[self.idx..].chars().next() {
+                        self.idx += escaped.len_utf8();
+                    }
+                }
+                Some(_) => self.idx += 1,
+            }
+        }
+        tokens.push(Token::new(TokenType::Str, start_idx, self.idx));
+    }
+}
+
+/// Words with a special meaning in expressions.
+pub const KEYWORDS: &[&str] = &[
+    "and", "as", "def", "false", "import", "include", "let", "not", "null", "or", "true",
+];
+
+#[derive(Debug, PartialEq)]
+pub enum TokenType {
+    Str,
+    Number,
+    Keyword,
+    FcnName,
+    Identifier,
+    OpenBracket,
+    CloseBracket,
+}
+
+/// A piece of the text found by `Parser::tokenize()`.
+#[derive(Debug)]
+pub struct Token {
+    pub typ: TokenType,
+    pub span: Span,
+}
+
+impl Token {
+    fn new(typ: TokenType, start: usize, end: usize) -> Self {
+        Token {
+            typ,
+            span: Span::new(start, end),
+        }
     }
 }
 
@@ -1138,18 +1612,48 @@ impl<'a> Parser<'a> {
 pub struct ParseError {
     idx: usize,
     typ: ParseErrorType,
+    opened_at: Option<(usize, usize)>,
+    text: String,
 }
 
 impl ParseError {
     pub fn to_string(&self) -> String {
-        format!("Parser error '{:?}' at index {}", self.typ, self.idx)
+        let (line, col) = line_col(self.text.as_str(), self.idx);
+        format!(
+            "Parse error at line {}, column {}: {}",
+            line,
+            col,
+            self.typ.message()
+        )
+    }
+
+    /// Whether the error is an unclosed bracket, brace, paren or string at the
+    /// end of the text, meaning that more input could make it parse.
+    pub fn is_unclosed_at_end(&self) -> bool {
+        self.opened_at.is_some() && self.idx >= self.text.trim_end().len()
+    }
+
+    /// Renders the error message followed by the offending source line with a
+    /// caret under the error position and dashes under the opening delimiter.
+    pub fn render(&self) -> String {
+        let mut markers = Vec::<(usize, usize, char)>::new();
+        if let Some((start, end)) = self.opened_at {
+            markers.push((start, end, '-'));
+        }
+        markers.push((self.idx, self.idx + 1, '^'));
+
+        format!(
+            "{}\n{}",
+            self.to_string(),
+            render_snippet(self.text.as_str(), markers.as_slice())
+        )
     }
 }
 
 #[derive(Debug)]
 enum ParseErrorType {
     NoClosingParen,
-    NoClosingParenFcnCall,
+    NoClosingParenFcnCall(String),
     NoParenContents,
     NoExprAfterOperator,
     NoColonInMapLiteral,
@@ -1174,6 +1678,117 @@ enum ParseErrorType {
     NoIdentifierInLetStmt,
     NoEqualsInLetStmt,
     NoExprInLetStmt,
+    NoClosingParenInFcnDef,
+    NoEqualsInFcnDef,
+    NoExprInFcnDef,
+    NoSemicolonAfterFcnDef,
+    NoIdentifierAfterNamespace,
+    NoAliasInImport,
+    NoSemicolonAfterImport,
     MissingFractionPartInFloatLiteral,
     UnableToParseType,
 }
+
+impl ParseErrorType {
+    fn message(&self) -> String {
+        match self {
+            ParseErrorType::NoClosingParen => {
+                "expected ')' to close the '(' opened here".to_string()
+            }
+            ParseErrorType::NoClosingParenFcnCall(name) => {
+                format!("expected ')' to close call to {}( opened here", name)
+            }
+            ParseErrorType::NoParenContents => "expected an expression inside '()'".to_string(),
+            ParseErrorType::NoExprAfterOperator => {
+                "expected an expression after the operator".to_string()
+            }
+            ParseErrorType::NoColonInMapLiteral => "expected ':' after the map key".to_string(),
+            ParseErrorType::NoMapLiteralValue => {
+                "expected a value after ':' in the map".to_string()
+            }
+            ParseErrorType::NoMapLiteralEndingBrace => {
+                "expected '}' to close the map opened here".to_string()
+            }
+            ParseErrorType::NoListLiteralEndingBracket => {
+                "expected ']' to close the list opened here".to_string()
+            }
+            ParseErrorType::NoIdentifierAfterDotAccess => {
+                "expected a field name after '.'".to_string()
+            }
+            ParseErrorType::NoClosingBracketForBracketAccess => {
+                "expected ']' to close the access opened here".to_string()
+            }
+            ParseErrorType::NoExpressionForBracketAccess => {
+                "expected an index or a slice inside '[]'".to_string()
+            }
+            ParseErrorType::NoClosingQuoteOnString => {
+                "expected a closing quote for the string opened here".to_string()
+            }
+            ParseErrorType::NoExprInFormatString => {
+                "expected an expression inside '{}' in the string".to_string()
+            }
+            ParseErrorType::NoClosingBraceInFormatString => {
+                "expected '}' to close the string interpolation opened here".to_string()
+            }
+            ParseErrorType::IncompleteParse => {
+                "unexpected input after the end of the expression".to_string()
+            }
+            ParseErrorType::NoExprReverseIndex => "expected an index after '/'".to_string(),
+            ParseErrorType::NoExpressionAfterMapSpread => {
+                "expected a map to spread after '*'".to_string()
+            }
+            ParseErrorType::NoExpressionAfterMapDelete => {
+                "expected a key to delete after '-'".to_string()
+            }
+            ParseErrorType::NoExprInMapKey => "expected a key expression inside '[]'".to_string(),
+            ParseErrorType::NoClosingBracketForMapKey => {
+                "expected ']' to close the map key opened here".to_string()
+            }
+            ParseErrorType::NoIdentifierAfterKeywordArgument => {
+                "expected a keyword argument name after ':'".to_string()
+            }
+            ParseErrorType::NoWhitespaceAfterKeywordArgumentKeyword => {
+                "expected whitespace after the keyword argument name".to_string()
+            }
+            ParseErrorType::NoExprAfterUnaryOperator => {
+                "expected an expression after the unary operator".to_string()
+            }
+            ParseErrorType::NoIdentifierInLetStmt => {
+                "expected a variable name after 'let'".to_string()
+            }
+            ParseErrorType::NoEqualsInLetStmt => {
+                "expected '=' after the variable name in 'let'".to_string()
+            }
+            ParseErrorType::NoExprInLetStmt => {
+                "expected an expression after '=' in 'let'".to_string()
+            }
+            ParseErrorType::NoClosingParenInFcnDef => {
+                "expected ')' to close the parameters opened here".to_string()
+            }
+            ParseErrorType::NoEqualsInFcnDef => {
+                "expected '=' after the parameters in 'def'".to_string()
+            }
+            ParseErrorType::NoExprInFcnDef => {
+                "expected an expression after '=' in 'def'".to_string()
+            }
+            ParseErrorType::NoSemicolonAfterFcnDef => {
+                "expected ';' after the function body in 'def'".to_string()
+            }
+            ParseErrorType::NoIdentifierAfterNamespace => {
+                "expected a function name after '::'".to_string()
+            }
+            ParseErrorType::NoAliasInImport => {
+                "expected 'as' and a module name after the path in 'import'".to_string()
+            }
+            ParseErrorType::NoSemicolonAfterImport => {
+                "expected ';' after the file path".to_string()
+            }
+            ParseErrorType::MissingFractionPartInFloatLiteral => {
+                "expected digits after the decimal point".to_string()
+            }
+            ParseErrorType::UnableToParseType => {
+                "expected a type such as %int, %str, %[int] or %{key: int}".to_string()
+            }
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use crate::parser::Parser;
+use crate::val::{ValType, WriteOptions};
+use crate::EvalCtx;
+
+use std::error::Error;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::time::Instant;
+
+const COMMANDS: &[(&str, &str)] = &[
+    (":vars", "List the variables in scope"),
+    (":type", "Show the type of the current value"),
+    (":undo", "Go back to the context before the last change"),
+    (
+        ":load file.kj",
+        "Evaluate a file on the current value and keep its definitions",
+    ),
+    (":save out.json", "Write the current value to a file"),
+    (":reset", "Go back to the context the REPL started with"),
+    (
+        ":help [fn_name]",
+        "List the commands or describe a function",
+    ),
+    (
+        ":time expr",
+        "Evaluate an expression and show how long it took",
+    ),
+    (":quit", "Exit the REPL"),
+];
+
+/// Contexts older than this are dropped from the undo stack.
+const MAX_UNDO: usize = 100;
+
+/// Longest preview of a value shown by `:vars`.
+const MAX_PREVIEW_CHARS: usize = 60;
+
+/// The state of a REPL session: the current context, the contexts that
+/// `:undo` can go back to and the one `:reset` goes back to.
+pub struct ReplSession {
+    pub ctx: EvalCtx,
+    initial_ctx: EvalCtx,
+    undo_stack: Vec<EvalCtx>,
+    /// Values are colored when stdout is a terminal.
+    write_options: WriteOptions,
+}
+
+impl ReplSession {
+    pub fn new(ctx: EvalCtx) -> Self {
+        ReplSession {
+            initial_ctx: ctx.clone(),
+            ctx,
+            undo_stack: Vec::new(),
+            write_options: WriteOptions {
+                color: std::io::stdout().is_terminal(),
+                ..WriteOptions::new(true)
+            },
+        }
+    }
+
+    /// Runs a line of input, which is either an expression or a colon command.
+    pub fn run_line(&mut self, line: &str) -> Result<(), Box<dyn Error>> {
+        let command = match line.trim_start().strip_prefix(':') {
+            None => return self.eval_line(line),
+            Some(command) => command.trim(),
+        };
+        let (name, arg) = match command.split_once(char::is_whitespace) {
+            None => (command, ""),
+            Some((name, arg)) => (name, arg.trim()),
+        };
+
+        let mut stdout = std::io::stdout();
+        match (name, arg) {
+            ("vars", "") => {
+                for (name, val) in self.ctx.variables() {
+                    let mut buffer = Vec::<u8>::new();
+                    EvalCtx::write_val(val, &mut buffer, false)?;
+                    let preview = String::from_utf8_lossy(&buffer);
+                    let preview = preview.trim_end();
+                    if preview.chars().count() > MAX_PREVIEW_CHARS {
+                        let preview = preview.chars().take(MAX_PREVIEW_CHARS).collect::<String>();
+                        writeln!(stdout, "{} = {}...", name, preview)?;
+                    } else {
+                        writeln!(stdout, "{} = {}", name, preview)?;
+                    }
+                }
+            }
+            ("type", "") => {
+                self.ctx.val.infer_type().write_with_options(
+                    &mut stdout,
+                    0,
+                    &self.write_options,
+                )?;
+            }
+            ("undo", "") => match self.undo_stack.pop() {
+                None => eprintln!("Error: nothing to undo"),
+                Some(ctx) => {
+                    self.ctx = ctx;
+                    self.ctx
+                        .val
+                        .write_with_options(&mut stdout, 0, &self.write_options)?;
+                }
+            },
+            ("load", path) if !path.is_empty() => match self.ctx.with_file(Path::new(path)) {
+                Err(err) => eprintln!("Error: {}", err),
+                Ok(ctx) => {
+                    self.push_undo();
+                    self.ctx = ctx;
+                    self.ctx
+                        .val
+                        .write_with_options(&mut stdout, 0, &self.write_options)?;
+                }
+            },
+            ("save", path) if !path.is_empty() => {
+                let saved = std::fs::File::create(path)
+                    .and_then(|mut file| EvalCtx::write_val(&self.ctx.val, &mut file, true));
+                if let Err(err) = saved {
+                    eprintln!("Error: unable to write \"{}\": {}", path, err);
+                }
+            }
+            ("reset", "") => {
+                self.push_undo();
+                self.ctx = self.initial_ctx.clone();
+            }
+            ("help", "") => {
+                for (usage, description) in COMMANDS {
+                    writeln!(stdout, "{:<18}{}", usage, description)?;
+                }
+            }
+            ("help", fcn_name) => match self.ctx.describe_fcn(fcn_name) {
+                None => eprintln!("Error: unknown function \"{}\"", fcn_name),
+                Some(description) => writeln!(stdout, "{}", description)?,
+            },
+            ("time", expr) if !expr.is_empty() => {
+                let start = Instant::now();
+                self.eval_line(expr)?;
+                eprintln!("time: {:.3?}", start.elapsed());
+            }
+            _ => eprintln!("Error: unknown command \":{}\", see :help", command),
+        }
+        Ok(())
+    }
+
+    fn eval_line(&mut self, line: &str) -> Result<(), Box<dyn Error>> {
+        match Parser::new(line).external_parse_expr() {
+            None => {}
+            Some(Err(err)) => {
+                eprint!("{}", err.render());
+                return Ok(());
+            }
+            Some(Ok(ast)) => {
+                self.push_undo();
+                self.ctx = self.ctx.eval(&ast);
+            }
+        }
+
+        self.ctx
+            .val
+            .write_with_options(&mut std::io::stdout(), 0, &self.write_options)?;
+        if let ValType::Err(err) = self.ctx.val.get_val() {
+            eprint!("{}", err.render(line));
+        }
+        Ok(())
+    }
+
+    fn push_undo(&mut self) {
+        if self.undo_stack.len() == MAX_UNDO {
+            self.undo_stack.remove(0);
+        }
+        self.undo_stack.push(self.ctx.clone());
+    }
+}
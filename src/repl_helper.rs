@@ -0,0 +1,233 @@
+use crate::parser::{Parser, Token, TokenType};
+use crate::val::{Val, ValType};
+use crate::EvalCtx;
+
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::{Hint, Hinter};
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::Helper;
+use std::borrow::Cow;
+use std::io::Write;
+use std::time::Duration;
+
+const STR_COLOR: &str = "\x1b[32m";
+const NUMBER_COLOR: &str = "\x1b[33m";
+const KEYWORD_COLOR: &str = "\x1b[35m";
+const FCN_NAME_COLOR: &str = "\x1b[34m";
+const MATCHING_BRACKET_COLOR: &str = "\x1b[1;36m";
+const HINT_COLOR: &str = "\x1b[90m";
+const RESET_COLOR: &str = "\x1b[0m";
+
+/// How long the value of the line being typed can take to evaluate before
+/// the hint is skipped.
+const HINT_TIME_BUDGET: Duration = Duration::from_millis(50);
+
+/// Longest preview of a value shown in a hint.
+const MAX_HINT_BYTES: usize = 80;
+
+/// Line editor integration for the REPL.
+pub struct ReplHelper {
+    /// The REPL's context after the last evaluated line.
+    pub ctx: EvalCtx,
+}
+
+impl ReplHelper {
+    pub fn new(ctx: EvalCtx) -> Self {
+        ReplHelper { ctx }
+    }
+}
+
+impl Validator for ReplHelper {
+    /// Keeps reading lines while the input has an unclosed bracket, brace,
+    /// paren or string. Other parse errors are reported after Enter.
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        match Parser::new(ctx.input()).external_parse_expr() {
+            Some(Err(err)) if err.is_unclosed_at_end() => Ok(ValidationResult::Incomplete),
+            _ => Ok(ValidationResult::Valid(None)),
+        }
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.ctx.completions(line, pos))
+    }
+}
+
+/// A preview of the value of the line being typed. Unlike history hints it
+/// can't be accepted into the line.
+pub struct ValueHint(String);
+
+impl Hint for ValueHint {
+    fn display(&self) -> &str {
+        self.0.as_str()
+    }
+
+    fn completion(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = ValueHint;
+
+    /// Previews the value of the line, or the first error in it, when the
+    /// cursor is at the end of the line.
+    fn hint(&self, line: &str, pos: usize, _ctx: &rustyline::Context<'_>) -> Option<ValueHint> {
+        if pos < line.len() || line.trim_start().starts_with(':') {
+            return None;
+        }
+        let val = self.ctx.preview(line, HINT_TIME_BUDGET)?;
+
+        let preview = match first_err(&val) {
+            Some(msg) => format!("Error: {}", msg),
+            None => {
+                let mut writer = LimitedWriter {
+                    buffer: Vec::new(),
+                    limit: MAX_HINT_BYTES,
+                };
+                let is_truncated = EvalCtx::write_val(&val, &mut writer, false).is_err();
+                let mut preview = String::from_utf8_lossy(&writer.buffer).to_string();
+                if is_truncated {
+                    preview.push_str("...");
+                }
+                preview
+            }
+        };
+        let preview = preview
+            .chars()
+            .map(|ch| if ch.is_control() { ' ' } else { ch })
+            .collect::<String>();
+        Some(ValueHint(format!("  => {}", preview)))
+    }
+}
+
+impl Highlighter for ReplHelper {
+    /// Colors strings, numbers, keywords and function names, and the bracket
+    /// at the cursor along with the one matching it.
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        let tokens = Parser::new(line).tokenize();
+        let matching = matching_brackets(&tokens, pos);
+
+        let mut result = String::with_capacity(line.len() * 2);
+        let mut last_end = 0;
+        for (idx, token) in tokens.iter().enumerate() {
+            let color = match token.typ {
+                TokenType::Str => STR_COLOR,
+                TokenType::Number => NUMBER_COLOR,
+                TokenType::Keyword => KEYWORD_COLOR,
+                TokenType::FcnName => FCN_NAME_COLOR,
+                TokenType::OpenBracket | TokenType::CloseBracket
+                    if matching.is_some_and(|(left, right)| idx == left || idx == right) =>
+                {
+                    MATCHING_BRACKET_COLOR
+                }
+                _ => continue,
+            };
+            result.push_str(&line[last_end..token.span.start]);
+            result.push_str(color);
+            result.push_str(&line[token.span.start..token.span.end]);
+            result.push_str(RESET_COLOR);
+            last_end = token.span.end;
+        }
+        result.push_str(&line[last_end..]);
+        Cow::Owned(result)
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("{}{}{}", HINT_COLOR, hint, RESET_COLOR))
+    }
+
+    /// Every edit or cursor move can change the colors, e.g. by closing a
+    /// string or moving onto a bracket.
+    fn highlight_char(&self, _line: &str, _pos: usize, _forced: bool) -> bool {
+        true
+    }
+}
+
+/// Indices of the bracket token at the cursor (or just before it) and the
+/// bracket that matches it.
+fn matching_brackets(tokens: &[Token], pos: usize) -> Option<(usize, usize)> {
+    let is_bracket =
+        |token: &Token| matches!(token.typ, TokenType::OpenBracket | TokenType::CloseBracket);
+    let cursor_idx = tokens
+        .iter()
+        .position(|token| is_bracket(token) && token.span.start == pos)
+        .or_else(|| {
+            tokens
+                .iter()
+                .position(|token| is_bracket(token) && token.span.end == pos)
+        })?;
+
+    let mut depth: usize = 0;
+    if tokens[cursor_idx].typ == TokenType::OpenBracket {
+        for (idx, token) in tokens.iter().enumerate().skip(cursor_idx) {
+            match token.typ {
+                TokenType::OpenBracket => depth += 1,
+                TokenType::CloseBracket => depth -= 1,
+                _ => continue,
+            }
+            if depth == 0 {
+                return Some((cursor_idx, idx));
+            }
+        }
+    } else {
+        for (idx, token) in tokens.iter().enumerate().take(cursor_idx + 1).rev() {
+            match token.typ {
+                TokenType::CloseBracket => depth += 1,
+                TokenType::OpenBracket => depth -= 1,
+                _ => continue,
+            }
+            if depth == 0 {
+                return Some((idx, cursor_idx));
+            }
+        }
+    }
+    None
+}
+
+/// Message of the first error found in `val`, searching depth first.
+fn first_err(val: &Val) -> Option<&str> {
+    match val.get_val() {
+        ValType::Err(err) => Some(err.msg.as_str()),
+        ValType::List(vals) => vals.iter().find_map(first_err),
+        ValType::Map(map) => map
+            .get_kv_pair_slice()
+            .iter()
+            .find_map(|(key, val)| first_err(key).or_else(|| first_err(val))),
+        _ => None,
+    }
+}
+
+/// Collects up to `limit` bytes and then fails, so that serializing a large
+/// value for a hint stops early.
+struct LimitedWriter {
+    buffer: Vec<u8>,
+    limit: usize,
+}
+
+impl Write for LimitedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let room = self.limit - self.buffer.len();
+        if buf.len() > room {
+            self.buffer.extend_from_slice(&buf[..room]);
+            return Err(std::io::Error::other("hint is too long"));
+        }
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Helper for ReplHelper {}
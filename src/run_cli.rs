@@ -0,0 +1,520 @@
+use crate::decimal_json::DecimalJsonReader;
+use crate::parser::Parser;
+use crate::user_paths;
+use crate::val::{Val, ValType, WriteOptions};
+use crate::{run_repl, EvalCtx};
+
+use std::io::{IsTerminal, Write};
+use std::process::{Child, Command, Stdio};
+
+const USAGE: &str = "\
+Usage: kj [OPTIONS] [QUERY] [FILE...]
+
+Evaluates QUERY once for every FILE, with the parsed JSON contents of the
+file as the input \".\". Without any FILE the query is evaluated once with
+null as the input, or once for every JSON value on stdin with --ndjson.
+A FILE of - reads stdin.
+Starts the REPL when called without a query.
+
+The variables and functions defined in ~/.config/kj/init.kj (or
+$XDG_CONFIG_HOME/kj/init.kj) are available to every query unless --no-init
+is given.
+
+When stdout is a terminal the output is colored and shown through $PAGER
+(less by default). Piped output is never colored, paged or truncated unless
+asked for.
+
+Exits with status 2 if an input can't be read and 3 if QUERY can't be parsed.
+
+Options:
+  -f, --from-file PATH  Read the query from PATH instead of the first argument
+  -c, --compact         Write the output without indentation
+      --ndjson          Evaluate QUERY once for every JSON value in the input
+                        stream and write one compact result per line
+      --decimal         Read numbers with a fraction in the query and input
+                        as exact decimals, so 0.1 + 0.2 is 0.3 and 19.990 is
+                        written back as it was
+  -o, --output FORMAT   Write the result as json (the default), yaml, toml,
+                        csv, tsv, table, ndjson or raw
+  -r, --raw             Write top-level strings without quotes, the same as
+                        --output raw
+      --color WHEN      Color the output: auto, always or never
+      --max-items N     Show at most N elements of each list and map,
+                        followed by a count of the rest
+      --max-depth N     Replace lists and maps nested deeper than N with a
+                        count of their elements
+      --no-pager        Don't show terminal output through $PAGER
+  -e, --exit-status     Exit with status 1 if the last output is an error
+      --arg NAME VALUE  Set the variable NAME to the string VALUE
+      --argjson NAME JSON
+                        Set the variable NAME to the parsed JSON value
+      --argfile NAME PATH
+                        Set the variable NAME to the parsed JSON contents of PATH
+      --no-init         Don't load the init file
+  -h, --help            Print this help message
+";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Json,
+    Yaml,
+    Toml,
+    Csv,
+    Tsv,
+    Table,
+    Ndjson,
+    Raw,
+}
+
+impl OutputFormat {
+    fn parse(name: &str) -> Option<OutputFormat> {
+        match name {
+            "json" => Some(OutputFormat::Json),
+            "yaml" => Some(OutputFormat::Yaml),
+            "toml" => Some(OutputFormat::Toml),
+            "csv" => Some(OutputFormat::Csv),
+            "tsv" => Some(OutputFormat::Tsv),
+            "table" => Some(OutputFormat::Table),
+            "ndjson" => Some(OutputFormat::Ndjson),
+            "raw" => Some(OutputFormat::Raw),
+            _ => None,
+        }
+    }
+
+    /// The builtin that converts a result to text in this format, if it
+    /// isn't written directly.
+    fn serializer(&self, val: &Val) -> Option<&'static str> {
+        match self {
+            OutputFormat::Yaml => Some("to_yaml"),
+            OutputFormat::Toml => Some("to_toml"),
+            OutputFormat::Csv => Some("to_csv"),
+            OutputFormat::Tsv => Some("to_tsv"),
+            OutputFormat::Table => Some("to_text_table"),
+            // Anything other than a list is written as a single line of JSON
+            OutputFormat::Ndjson if matches!(val.get_val(), ValType::List(_)) => Some("to_ndjson"),
+            _ => None,
+        }
+    }
+}
+
+pub struct CliArgs {
+    pub query: Option<String>,
+    pub query_file: Option<String>,
+    pub files: Vec<String>,
+    pub variables: Vec<(String, Val)>,
+    pub compact: bool,
+    pub ndjson: bool,
+    pub decimal: bool,
+    pub output: OutputFormat,
+    /// None to color the output only when stdout is a terminal.
+    pub color: Option<bool>,
+    pub max_items: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub no_pager: bool,
+    pub exit_status: bool,
+    pub no_init: bool,
+    pub help: bool,
+}
+
+impl CliArgs {
+    pub fn parse(args: &[String]) -> Result<CliArgs, String> {
+        let mut result = CliArgs {
+            query: None,
+            query_file: None,
+            files: Vec::new(),
+            variables: Vec::new(),
+            compact: false,
+            ndjson: false,
+            decimal: false,
+            output: OutputFormat::Json,
+            color: None,
+            max_items: None,
+            max_depth: None,
+            no_pager: false,
+            exit_status: false,
+            no_init: false,
+            help: false,
+        };
+
+        // The flag, name and value of each --arg, --argjson and --argfile,
+        // converted once --decimal is known
+        let mut raw_variables = Vec::<(&str, String, &str)>::new();
+        let mut positional = Vec::<String>::new();
+        let mut only_positional = false;
+        let mut idx = 0;
+        while idx < args.len() {
+            let arg = &args[idx];
+            idx += 1;
+
+            if only_positional || arg == "-" || !arg.starts_with('-') {
+                positional.push(arg.clone());
+                continue;
+            }
+
+            match arg.as_str() {
+                "--" => {
+                    only_positional = true;
+                }
+                "-f" | "--from-file" => {
+                    if idx >= args.len() {
+                        return Err(format!("{} requires a file path", arg));
+                    }
+                    result.query_file = Some(args[idx].clone());
+                    idx += 1;
+                }
+                "--arg" | "--argjson" | "--argfile" => {
+                    if idx + 1 >= args.len() {
+                        return Err(format!("{} requires a name and a value", arg));
+                    }
+                    raw_variables.push((arg.as_str(), args[idx].clone(), args[idx + 1].as_str()));
+                    idx += 2;
+                }
+                "-c" | "--compact" => {
+                    result.compact = true;
+                }
+                "--ndjson" | "--jsonl" => {
+                    result.ndjson = true;
+                }
+                "--decimal" => {
+                    result.decimal = true;
+                }
+                "-o" | "--output" => {
+                    result.output = match args.get(idx).and_then(|name| OutputFormat::parse(name)) {
+                        None => {
+                            return Err(format!(
+                                "{} requires json, yaml, toml, csv, tsv, table, ndjson or raw",
+                                arg
+                            ))
+                        }
+                        Some(output) => output,
+                    };
+                    idx += 1;
+                }
+                "-r" | "--raw" => {
+                    result.output = OutputFormat::Raw;
+                }
+                "--color" => {
+                    result.color = match args.get(idx).map(|when| when.as_str()) {
+                        Some("auto") => None,
+                        Some("always") => Some(true),
+                        Some("never") => Some(false),
+                        _ => return Err(format!("{} requires auto, always or never", arg)),
+                    };
+                    idx += 1;
+                }
+                "--max-items" | "--max-depth" => {
+                    let limit = match args.get(idx).and_then(|limit| limit.parse().ok()) {
+                        None => return Err(format!("{} requires a number", arg)),
+                        Some(limit) => limit,
+                    };
+                    idx += 1;
+                    if arg == "--max-items" {
+                        result.max_items = Some(limit);
+                    } else {
+                        result.max_depth = Some(limit);
+                    }
+                }
+                "--no-pager" => {
+                    result.no_pager = true;
+                }
+                "-e" | "--exit-status" => {
+                    result.exit_status = true;
+                }
+                "--no-init" => {
+                    result.no_init = true;
+                }
+                "-h" | "--help" => {
+                    result.help = true;
+                }
+                _ => return Err(format!("Unknown option \"{}\"", arg)),
+            }
+        }
+
+        for (arg, name, value) in raw_variables {
+            let val = match arg {
+                "--arg" => Val::new_str(value),
+                "--argjson" => Val::from_json_str(value, result.decimal),
+                _ => match std::fs::read_to_string(value) {
+                    Err(err) => return Err(format!("unable to read \"{}\": {}", value, err)),
+                    Ok(contents) => Val::from_json_str(contents.as_str(), result.decimal),
+                },
+            };
+            if let ValType::Err(err) = val.get_val() {
+                return Err(format!("invalid value for {} {}: {}", arg, name, err.msg));
+            }
+            result.variables.push((name, val));
+        }
+
+        let mut positional = positional.into_iter();
+        if result.query_file.is_none() {
+            result.query = positional.next();
+        }
+        result.files = positional.collect();
+
+        Ok(result)
+    }
+}
+
+pub fn run_cli(args: &[String]) -> i32 {
+    let args = match CliArgs::parse(args) {
+        Err(err) => {
+            eprintln!("kj: {}\n\n{}", err, USAGE);
+            return 2;
+        }
+        Ok(args) => args,
+    };
+
+    if args.help {
+        print!("{}", USAGE);
+        return 0;
+    }
+
+    let mut ctx = EvalCtx::new().with_decimal(args.decimal);
+    if !args.no_init {
+        if let Some(init_file) = user_paths::init_file().filter(|path| path.is_file()) {
+            ctx = match ctx.with_file(&init_file) {
+                Err(err) => {
+                    eprintln!("kj: {} (use --no-init to skip the init file)", err);
+                    return 2;
+                }
+                Ok(ctx) => ctx.with_val(Val::new_null()),
+            };
+        }
+    }
+    for (name, val) in &args.variables {
+        ctx = ctx.with_variable(name.as_str(), val.clone());
+    }
+
+    let query = match (&args.query, &args.query_file) {
+        (_, Some(query_file)) => match std::fs::read_to_string(query_file) {
+            Err(err) => {
+                eprintln!("kj: unable to read query file \"{}\": {}", query_file, err);
+                return 2;
+            }
+            Ok(query) => query,
+        },
+        (Some(query), None) => query.clone(),
+        (None, None) => {
+            return match run_repl(ctx) {
+                Err(err) => {
+                    eprintln!("kj: {}", err);
+                    1
+                }
+                Ok(_) => 0,
+            };
+        }
+    };
+
+    if let Some(query_file) = &args.query_file {
+        ctx = ctx.with_source_path(query_file.as_str());
+    }
+    let is_terminal = std::io::stdout().is_terminal();
+    let opts = WriteOptions {
+        use_indent: !args.compact && !args.ndjson && args.output != OutputFormat::Ndjson,
+        color: args.color.unwrap_or(is_terminal),
+        max_items: args.max_items,
+        max_depth: args.max_depth,
+    };
+    let ast = match Parser::new(query.as_str()).external_parse_expr() {
+        None => None,
+        Some(Err(err)) => {
+            eprint!("{}", err.render());
+            return 3;
+        }
+        Some(Ok(ast)) => Some(ast),
+    };
+
+    // Nothing returns early from here on, so the pager is always waited for
+    let mut pager = if is_terminal && !args.no_pager {
+        start_pager()
+    } else {
+        None
+    };
+    let mut output: Box<dyn Write> = match pager.as_mut().and_then(|pager| pager.stdin.take()) {
+        None => Box::new(std::io::stdout().lock()),
+        Some(pager_stdin) => Box::new(pager_stdin),
+    };
+
+    let mut last_is_err = false;
+    let mut process_input = |input: Val| -> Result<(), i32> {
+        let result = match &ast {
+            None => Val::new_null(),
+            Some(ast) => ctx.with_val(input).eval(ast).val,
+        };
+        last_is_err = false;
+        if let ValType::Err(err) = result.get_val() {
+            last_is_err = true;
+            eprint!("{}", err.render(query.as_str()));
+        }
+
+        let result = match args.output.serializer(&result) {
+            None => result,
+            Some(serializer) => {
+                let serialized = ctx.with_val(result).eval_fcn(serializer, &Vec::new());
+                if let ValType::Err(err) = serialized.get_val() {
+                    if last_is_err {
+                        // The error was already reported and can't be serialized
+                        return Ok(());
+                    }
+                    eprintln!("kj: unable to write the output: {}", err.msg);
+                    return Err(2);
+                }
+                // Written as bytes since the serializers end with a newline
+                match serialized.get_val() {
+                    ValType::String(text) => Val::new_bytes(text.as_bytes().to_vec()),
+                    _ => serialized,
+                }
+            }
+        };
+        let raw = args.output == OutputFormat::Raw;
+        match write_output(&result, raw, &opts, &mut output) {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                if err.kind() == std::io::ErrorKind::BrokenPipe {
+                    return Err(0);
+                }
+                eprintln!("kj: unable to write output: {}", err);
+                Err(2)
+            }
+        }
+    };
+
+    let result = if args.ndjson {
+        if args.files.is_empty() {
+            stream_json_values(
+                std::io::stdin().lock(),
+                "stdin",
+                args.decimal,
+                &mut process_input,
+            )
+        } else {
+            args.files.iter().try_for_each(|file| {
+                if file == "-" {
+                    return stream_json_values(
+                        std::io::stdin().lock(),
+                        "stdin",
+                        args.decimal,
+                        &mut process_input,
+                    );
+                }
+                match std::fs::File::open(file) {
+                    Err(err) => {
+                        eprintln!("kj: unable to read \"{}\": {}", file, err);
+                        Err(2)
+                    }
+                    Ok(fp) => stream_json_values(fp, file, args.decimal, &mut process_input),
+                }
+            })
+        }
+    } else if args.files.is_empty() {
+        process_input(Val::new_null())
+    } else {
+        args.files.iter().try_for_each(|file| {
+            let contents = if file == "-" {
+                std::io::read_to_string(std::io::stdin())
+            } else {
+                std::fs::read_to_string(file)
+            };
+            let contents = match contents {
+                Err(err) => {
+                    eprintln!("kj: unable to read \"{}\": {}", file, err);
+                    return Err(2);
+                }
+                Ok(contents) => contents,
+            };
+            let input = Val::from_json_str(contents.as_str(), args.decimal);
+            if let ValType::Err(err) = input.get_val() {
+                eprintln!("kj: unable to read \"{}\": {}", file, err.msg);
+                return Err(2);
+            }
+            process_input(input)
+        })
+    };
+
+    // Closing the pager's stdin lets it know the output is complete
+    drop(output);
+    if let Some(mut pager) = pager {
+        let _ = pager.wait();
+    }
+
+    match result {
+        Err(code) => code,
+        Ok(_) => {
+            if args.exit_status && last_is_err {
+                1
+            } else {
+                0
+            }
+        }
+    }
+}
+
+/// Parses newline-delimited or concatenated JSON values one at a time so that
+/// memory use does not depend on the size of the input.
+fn stream_json_values(
+    reader: impl std::io::Read,
+    name: &str,
+    decimal: bool,
+    callback: &mut impl FnMut(Val) -> Result<(), i32>,
+) -> Result<(), i32> {
+    let inputs: Box<dyn Iterator<Item = Result<Val, String>>> = if decimal {
+        Box::new(DecimalJsonReader::new(reader))
+    } else {
+        Box::new(
+            serde_json::Deserializer::from_reader(std::io::BufReader::new(reader))
+                .into_iter::<Val>()
+                .map(|input| input.map_err(|err| err.to_string())),
+        )
+    };
+    for input in inputs {
+        match input {
+            Err(err) => {
+                eprintln!("kj: unable to parse JSON from {}: {}", name, err);
+                return Err(2);
+            }
+            Ok(input) => callback(input)?,
+        }
+    }
+    Ok(())
+}
+
+/// Starts `$PAGER` (or less) to show the output, returning None if paging is
+/// turned off with an empty `$PAGER` or the pager can't be started.
+fn start_pager() -> Option<Child> {
+    let pager = std::env::var("PAGER").unwrap_or("less".to_string());
+    if pager.trim().is_empty() || pager.trim() == "cat" {
+        return None;
+    }
+
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(pager).stdin(Stdio::piped());
+    // Quit if the output fits on one screen and show the colors
+    if std::env::var_os("LESS").is_none() {
+        cmd.env("LESS", "FRX");
+    }
+    cmd.spawn().ok()
+}
+
+fn write_output(
+    val: &Val,
+    raw: bool,
+    opts: &WriteOptions,
+    writer: &mut impl Write,
+) -> std::io::Result<()> {
+    match val.get_val() {
+        ValType::String(text) if raw => {
+            writer.write_all(text.as_bytes())?;
+            writer.write_all(b"\n")?;
+        }
+        ValType::Bytes(_) => {
+            EvalCtx::write_val(val, writer, true)?;
+        }
+        _ => {
+            val.write_with_options(writer, 0, opts)?;
+            if !opts.use_indent {
+                writer.write_all(b"\n")?;
+            }
+        }
+    }
+    writer.flush()
+}
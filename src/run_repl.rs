@@ -1,26 +1,50 @@
+use crate::repl_commands::ReplSession;
+use crate::repl_helper::ReplHelper;
+use crate::user_paths;
 use crate::EvalCtx;
 
+use rustyline::history::DefaultHistory;
+use rustyline::Editor;
 use std::error::Error;
 
-pub fn run_repl() -> Result<(), Box<dyn Error>> {
-    let mut rl = rustyline::DefaultEditor::new()?;
-    let mut stdout = std::io::stdout();
-    let mut ctx = EvalCtx::new();
+pub fn run_repl(ctx: EvalCtx) -> Result<(), Box<dyn Error>> {
+    let config = rustyline::Config::builder()
+        .max_history_size(10_000)?
+        .build();
+    let mut rl = Editor::<ReplHelper, DefaultHistory>::with_config(config)?;
+    rl.set_helper(Some(ReplHelper::new(ctx.clone())));
+
+    let history_file = user_paths::history_file();
+    if let Some(history_file) = &history_file {
+        // The history file doesn't exist the first time the REPL is run
+        let _ = rl.load_history(history_file);
+    }
+
+    let mut session = ReplSession::new(ctx);
     loop {
         let readline = rl.readline("> ");
         match readline {
             Ok(line) => {
-                if line == "quit" {
-                    return Ok(());
+                if line == "quit" || line.trim() == ":quit" {
+                    break;
                 }
                 rl.add_history_entry(&line)?;
-                ctx = ctx.parse_and_eval(line.as_str());
-
-                EvalCtx::write_val(&ctx.val, &mut stdout, true)?;
+                session.run_line(line.as_str())?;
+                if let Some(helper) = rl.helper_mut() {
+                    helper.ctx = session.ctx.clone();
+                }
             }
             Err(_) => {
-                return Ok(());
+                break;
             }
         }
     }
+
+    if let Some(history_file) = &history_file {
+        if let Some(dir) = history_file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        rl.save_history(history_file)?;
+    }
+    Ok(())
 }
@@ -0,0 +1,30 @@
+use std::path::PathBuf;
+
+/// Directory for user configuration, `$XDG_CONFIG_HOME/kj` or `~/.config/kj`.
+pub fn config_dir() -> Option<PathBuf> {
+    base_dir("XDG_CONFIG_HOME", ".config").map(|dir| dir.join("kj"))
+}
+
+/// Path of the init file that is evaluated at startup.
+pub fn init_file() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("init.kj"))
+}
+
+/// Directory for user data, `$XDG_DATA_HOME/kj` or `~/.local/share/kj`.
+pub fn data_dir() -> Option<PathBuf> {
+    base_dir("XDG_DATA_HOME", ".local/share").map(|dir| dir.join("kj"))
+}
+
+/// Path of the file the REPL history is saved to.
+pub fn history_file() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join("history"))
+}
+
+fn base_dir(xdg_var: &str, home_subdir: &str) -> Option<PathBuf> {
+    match std::env::var_os(xdg_var) {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => std::env::var_os("HOME")
+            .filter(|home| !home.is_empty())
+            .map(|home| PathBuf::from(home).join(home_subdir)),
+    }
+}
@@ -1,8 +1,15 @@
+use crate::ast_node::Span;
+use crate::decimal_json::DecimalJsonReader;
+use crate::diagnostic::{line_col, render_snippet};
 use base64::{engine::general_purpose::STANDARD, Engine as _};
+use bigdecimal::{BigDecimal, ToPrimitive, Zero};
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, SecondsFormat, TimeZone};
+use chrono_tz::Tz;
 use std::cmp::Ordering;
 use std::collections::hash_map::Entry;
 use std::hash::Hash;
 use std::hash::Hasher;
+use std::str::FromStr;
 use std::{cell::OnceCell, collections::HashMap, hash::DefaultHasher, rc::Rc};
 
 #[derive(Clone)]
@@ -17,10 +24,16 @@ struct InnerVal {
 
 pub enum ValType {
     Null,
-    Err(String),
+    Err(ErrVal),
+    Int(i64),
     Float64(f64),
+    /// An exact decimal, such as a number read in decimal mode.
+    Decimal(BigDecimal),
     Bool(bool),
     String(String),
+    /// A point in time with the UTC offset it was read or converted with, and
+    /// the timezone it was converted to with to_timezone(), if any.
+    Datetime(DateTime<FixedOffset>, Option<Zone>),
     List(Vec<Val>),
     Map(OrderedMap),
     Bytes(Vec<u8>),
@@ -36,6 +49,55 @@ pub enum ValType {
     OptionalType(Val),
 }
 
+pub struct ErrVal {
+    pub msg: String,
+    /// Span of the innermost expression that produced the error.
+    pub span: Option<Span>,
+    /// Names of the functions and operators being evaluated when the error
+    /// was produced, from outermost to innermost.
+    pub trace: Vec<String>,
+}
+
+impl ErrVal {
+    /// Renders the error with the failing part of the query `text` underlined.
+    pub fn render(&self, text: &str) -> String {
+        let span = match self.span {
+            None => return format!("Error: {}\n", self.msg),
+            Some(span) => span,
+        };
+        let (line, col) = line_col(text, span.start);
+        let mut result = format!(
+            "Error at line {}, column {}: {}\n{}",
+            line,
+            col,
+            self.msg,
+            render_snippet(text, &[(span.start, span.end, '^')])
+        );
+        if !self.trace.is_empty() {
+            result.push_str(format!("in {}\n", self.short_trace().join(" -> ")).as_str());
+        }
+        result
+    }
+
+    /// The trace with the middle left out when it's long, as it is after
+    /// runaway recursion.
+    fn short_trace(&self) -> Vec<String> {
+        if self.trace.len() <= 2 * TRACE_ENDS_LEN {
+            return self.trace.clone();
+        }
+        let mut trace = self.trace[..TRACE_ENDS_LEN].to_vec();
+        trace.push(format!(
+            "... {} more",
+            self.trace.len() - 2 * TRACE_ENDS_LEN
+        ));
+        trace.extend_from_slice(&self.trace[self.trace.len() - TRACE_ENDS_LEN..]);
+        trace
+    }
+}
+
+/// Calls shown at each end of a long trace.
+const TRACE_ENDS_LEN: usize = 5;
+
 impl Val {
     pub fn get_val(&self) -> &ValType {
         &self.inner_val.val_type
@@ -55,13 +117,77 @@ impl Val {
     }
 
     pub fn new_err(msg: &str) -> Val {
-        Val::new(ValType::Err(msg.to_string()))
+        Val::new(ValType::Err(ErrVal {
+            msg: msg.to_string(),
+            span: None,
+            trace: Vec::new(),
+        }))
+    }
+
+    pub fn new_int(val: i64) -> Val {
+        Val::new(ValType::Int(val))
     }
 
     pub fn new_f64(val: f64) -> Val {
         Val::new(ValType::Float64(val))
     }
 
+    pub fn new_datetime(val: DateTime<FixedOffset>) -> Val {
+        Val::new(ValType::Datetime(val, None))
+    }
+
+    /// Creates a datetime that keeps `zone`, with the offset the zone has at
+    /// that point in time.
+    pub fn new_zoned_datetime(val: DateTime<FixedOffset>, zone: Option<Zone>) -> Val {
+        match zone {
+            Some(zone) => Val::new(ValType::Datetime(zone.convert(&val), Some(zone))),
+            None => Val::new_datetime(val),
+        }
+    }
+
+    pub fn new_decimal(val: BigDecimal) -> Val {
+        Val::new(ValType::Decimal(val))
+    }
+
+    /// Parses a number as an integer if it fits in an i64. Larger integers, and
+    /// any number in decimal mode, are parsed as decimals and the rest as
+    /// floats.
+    pub fn parse_number(text: &str, decimal: bool) -> Option<Val> {
+        if let Ok(val) = text.parse::<i64>() {
+            return Some(Val::new_int(val));
+        }
+        let is_integer = text
+            .strip_prefix('-')
+            .unwrap_or(text)
+            .bytes()
+            .all(|ch| ch.is_ascii_digit());
+        if decimal || is_integer {
+            return BigDecimal::from_str(text).ok().map(Val::new_decimal);
+        }
+        text.parse::<f64>().ok().map(Val::new_f64)
+    }
+
+    /// The value of an integer or float as a float.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self.get_val() {
+            ValType::Int(val) => Some(*val as f64),
+            ValType::Float64(val) => Some(*val),
+            ValType::Decimal(val) => val.to_f64(),
+            _ => None,
+        }
+    }
+
+    /// The value of a number as a decimal. Floats are converted from their
+    /// shortest representation, so `0.1` becomes exactly `0.1`.
+    pub fn as_decimal(&self) -> Option<BigDecimal> {
+        match self.get_val() {
+            ValType::Int(val) => Some(BigDecimal::from(*val)),
+            ValType::Float64(val) if val.is_finite() => BigDecimal::from_str(&val.to_string()).ok(),
+            ValType::Decimal(val) => Some(val.clone()),
+            _ => None,
+        }
+    }
+
     pub fn new_bool(val: bool) -> Val {
         Val::new(ValType::Bool(val))
     }
@@ -89,8 +215,10 @@ impl Val {
                 Null,
                 Err,
                 Float64,
+                Decimal,
                 Bool,
                 String,
+                Datetime,
                 List,
                 Map,
                 Bytes,
@@ -112,12 +240,28 @@ impl Val {
                 }
                 ValType::Err(val) => {
                     HashTypes::Err.hash(&mut hasher);
-                    val.hash(&mut hasher);
+                    val.msg.hash(&mut hasher);
+                }
+                // Integers hash like the equal float so that `1 == 1.0`
+                ValType::Int(val) => {
+                    HashTypes::Float64.hash(&mut hasher);
+                    (*val as f64).to_ne_bytes().hash(&mut hasher);
                 }
                 ValType::Float64(val) => {
                     HashTypes::Float64.hash(&mut hasher);
                     val.to_ne_bytes().hash(&mut hasher);
                 }
+                // Decimals equal to an integer or float hash like them
+                ValType::Decimal(val) => match decimal_as_exact_f64(val) {
+                    Some(val) => {
+                        HashTypes::Float64.hash(&mut hasher);
+                        val.to_ne_bytes().hash(&mut hasher);
+                    }
+                    None => {
+                        HashTypes::Decimal.hash(&mut hasher);
+                        val.normalized().to_string().hash(&mut hasher);
+                    }
+                },
                 ValType::Bool(val) => {
                     HashTypes::Bool.hash(&mut hasher);
                     val.hash(&mut hasher);
@@ -126,6 +270,12 @@ impl Val {
                     HashTypes::String.hash(&mut hasher);
                     val.hash(&mut hasher);
                 }
+                // Equal instants in different offsets hash the same
+                ValType::Datetime(val, _) => {
+                    HashTypes::Datetime.hash(&mut hasher);
+                    val.timestamp().hash(&mut hasher);
+                    val.timestamp_subsec_nanos().hash(&mut hasher);
+                }
                 ValType::List(val) => {
                     HashTypes::List.hash(&mut hasher);
                     for elem in val {
@@ -188,13 +338,24 @@ impl Val {
         writer: &mut impl std::io::Write,
         indent: u64,
         use_indent: bool,
+    ) -> std::io::Result<usize> {
+        self.write_with_options(writer, indent, &WriteOptions::new(use_indent))
+    }
+
+    /// Like `write_to_str()` but can color and truncate the output for
+    /// display in a terminal.
+    pub fn write_with_options(
+        &self,
+        writer: &mut impl std::io::Write,
+        indent: u64,
+        opts: &WriteOptions,
     ) -> std::io::Result<usize> {
         if let ValType::Bytes(bytes) = self.get_val() {
             writer.write(bytes.as_slice())?;
             return Ok(0);
         }
-        self.inner_write_str(writer, indent, use_indent)?;
-        if use_indent {
+        self.inner_write_str(writer, indent, opts)?;
+        if opts.use_indent {
             writer.write("\n".as_bytes())?;
         }
         Ok(0)
@@ -204,7 +365,7 @@ impl Val {
         &self,
         writer: &mut impl std::io::Write,
         indent: u64,
-        use_indent: bool,
+        opts: &WriteOptions,
     ) -> std::io::Result<usize> {
         fn write_indent(writer: &mut impl std::io::Write, indent: u64) -> std::io::Result<usize> {
             for _ in 0..indent {
@@ -215,44 +376,89 @@ impl Val {
 
         match self.get_val() {
             ValType::Null => {
-                writer.write("null".as_bytes())?;
+                write_colored(writer, opts, NULL_COLOR, |writer| {
+                    writer.write("null".as_bytes())
+                })?;
+            }
+            ValType::Int(val) => {
+                write_colored(writer, opts, NUMBER_COLOR, |writer| {
+                    writer.write(val.to_string().as_str().as_bytes())
+                })?;
+            }
+            ValType::Decimal(val) => {
+                write_colored(writer, opts, NUMBER_COLOR, |writer| {
+                    writer.write(val.to_plain_string().as_bytes())
+                })?;
+            }
+            // JSON has no NaN or infinity, so write null like serde_json does
+            ValType::Float64(val) if !val.is_finite() => {
+                write_colored(writer, opts, NULL_COLOR, |writer| {
+                    writer.write("null".as_bytes())
+                })?;
             }
             ValType::Float64(val) => {
                 // TODO: Don't allocate on every float write
-                writer.write(val.to_string().as_str().as_bytes())?;
+                write_colored(writer, opts, NUMBER_COLOR, |writer| {
+                    writer.write(val.to_string().as_str().as_bytes())
+                })?;
             }
             ValType::Bool(val) => {
-                if *val {
-                    writer.write("true".as_bytes())?;
-                } else {
-                    writer.write("false".as_bytes())?;
-                }
+                write_colored(writer, opts, BOOL_COLOR, |writer| {
+                    if *val {
+                        writer.write("true".as_bytes())
+                    } else {
+                        writer.write("false".as_bytes())
+                    }
+                })?;
             }
             ValType::String(val) => {
-                write_json_escaped_str(writer, val.as_str())?;
+                write_colored(writer, opts, STRING_COLOR, |writer| {
+                    write_json_escaped_str(writer, val.as_str())
+                })?;
+            }
+            ValType::Datetime(val, _) => {
+                write_colored(writer, opts, STRING_COLOR, |writer| {
+                    write_json_escaped_str(writer, datetime_to_iso(val).as_str())
+                })?;
             }
             ValType::Err(val) => {
-                writer.write("{\"ERROR\":".as_bytes())?;
-                write_json_escaped_str(writer, val.as_str())?;
-                writer.write("}".as_bytes())?;
+                write_colored(writer, opts, ERR_COLOR, |writer| {
+                    writer.write("{\"ERROR\":".as_bytes())?;
+                    write_json_escaped_str(writer, val.msg.as_str())?;
+                    writer.write("}".as_bytes())
+                })?;
+            }
+            ValType::List(val) if opts.is_too_deep(indent, val.len()) => {
+                write_colored(writer, opts, TRUNCATED_COLOR, |writer| {
+                    writer.write(format!("[... {}]", format_count(val.len(), "item")).as_bytes())
+                })?;
+            }
+            ValType::Map(val) if opts.is_too_deep(indent, val.len()) => {
+                write_colored(writer, opts, TRUNCATED_COLOR, |writer| {
+                    writer.write(format!("{{... {}}}", format_count(val.len(), "key")).as_bytes())
+                })?;
             }
             ValType::List(val) => {
                 writer.write("[".as_bytes())?;
-                for (idx, elem) in val.iter().enumerate() {
+                for (idx, elem) in val.iter().take(opts.item_limit()).enumerate() {
                     if idx > 0 {
-                        if use_indent {
+                        if opts.use_indent {
                             writer.write(", ".as_bytes())?;
                         } else {
                             writer.write(",".as_bytes())?;
                         }
                     }
-                    if use_indent {
+                    if opts.use_indent {
                         writer.write("\n".as_bytes())?;
                         write_indent(writer, indent + 1)?;
                     }
-                    elem.inner_write_str(writer, indent + 1, use_indent)?;
+                    elem.inner_write_str(writer, indent + 1, opts)?;
+                }
+                if val.len() > opts.item_limit() {
+                    let count = format_count(val.len() - opts.item_limit(), "more item");
+                    write_more_items(writer, indent + 1, opts, count.as_str())?;
                 }
-                if val.len() > 0 && use_indent {
+                if val.len() > 0 && opts.use_indent {
                     writer.write("\n".as_bytes())?;
                     write_indent(writer, indent)?;
                 }
@@ -260,47 +466,57 @@ impl Val {
             }
             ValType::Map(val) => {
                 writer.write("{".as_bytes())?;
-                for (idx, (key, val)) in val.pairs.iter().enumerate() {
+                for (idx, (key, val)) in val.pairs.iter().take(opts.item_limit()).enumerate() {
                     if idx > 0 {
-                        if use_indent {
+                        if opts.use_indent {
                             writer.write(", ".as_bytes())?;
                         } else {
                             writer.write(",".as_bytes())?;
                         }
                     }
-                    if use_indent {
+                    if opts.use_indent {
                         writer.write("\n".as_bytes())?;
                         write_indent(writer, indent + 1)?;
                     }
                     match key.get_val() {
-                        ValType::String(_) => {
-                            key.inner_write_str(writer, indent + 1, use_indent)?;
+                        ValType::String(key) => {
+                            write_colored(writer, opts, KEY_COLOR, |writer| {
+                                write_json_escaped_str(writer, key.as_str())
+                            })?;
                         }
                         _ => {
                             let mut temp_writer = Vec::<u8>::new();
-                            key.inner_write_str(&mut temp_writer, 0, false)?;
+                            key.inner_write_str(&mut temp_writer, 0, &WriteOptions::new(false))?;
                             let serialized_key =
                                 std::str::from_utf8(temp_writer.as_slice()).unwrap();
-                            write_json_escaped_str(writer, serialized_key)?;
+                            write_colored(writer, opts, KEY_COLOR, |writer| {
+                                write_json_escaped_str(writer, serialized_key)
+                            })?;
                         }
                     }
-                    if use_indent {
+                    if opts.use_indent {
                         writer.write(": ".as_bytes())?;
                     } else {
                         writer.write(":".as_bytes())?;
                     }
-                    val.inner_write_str(writer, indent + 1, use_indent)?;
+                    val.inner_write_str(writer, indent + 1, opts)?;
+                }
+                if val.len() > opts.item_limit() {
+                    let count = format_count(val.len() - opts.item_limit(), "more key");
+                    write_more_items(writer, indent + 1, opts, count.as_str())?;
                 }
-                if val.pairs.len() > 0 && use_indent {
+                if val.pairs.len() > 0 && opts.use_indent {
                     writer.write("\n".as_bytes())?;
                     write_indent(writer, indent)?;
                 }
                 writer.write("}".as_bytes())?;
             }
             ValType::Bytes(val) => {
-                writer.write("\"".as_bytes())?;
-                writer.write(STANDARD.encode(val).as_bytes())?;
-                writer.write("\"".as_bytes())?;
+                write_colored(writer, opts, STRING_COLOR, |writer| {
+                    writer.write("\"".as_bytes())?;
+                    writer.write(STANDARD.encode(val).as_bytes())?;
+                    writer.write("\"".as_bytes())
+                })?;
             }
             // Types
             ValType::IntType => {
@@ -368,11 +584,11 @@ impl Val {
                     }
                     match key.get_val() {
                         ValType::String(_) => {
-                            key.inner_write_str(writer, 0, false)?;
+                            key.inner_write_str(writer, 0, &WriteOptions::new(false))?;
                         }
                         _ => {
                             let mut temp_writer = Vec::<u8>::new();
-                            key.inner_write_str(&mut temp_writer, 0, false)?;
+                            key.inner_write_str(&mut temp_writer, 0, &WriteOptions::new(false))?;
                             let serialized_key =
                                 std::str::from_utf8(temp_writer.as_slice()).unwrap();
                             write_json_escaped_str(writer, serialized_key)?;
@@ -392,13 +608,37 @@ impl Val {
         Ok(0)
     }
 
-    pub fn from_json_str(json_str: &str) -> Self {
-        match serde_json::from_str::<Val>(json_str) {
+    /// Parses JSON, keeping numbers with a fraction as decimals in decimal
+    /// mode.
+    pub fn from_json_str(json_str: &str, decimal: bool) -> Self {
+        let val = if decimal {
+            DecimalJsonReader::new(json_str.as_bytes()).read_single()
+        } else {
+            serde_json::from_str::<Val>(json_str).map_err(|err| err.to_string())
+        };
+        match val {
             Ok(val) => val,
             Err(_) => Val::new_err("unable to parse JSON"),
         }
     }
 
+    pub fn from_ndjson_str(ndjson_str: &str, decimal: bool) -> Self {
+        let mut vals = Vec::<Val>::new();
+        let parsed = if decimal {
+            DecimalJsonReader::new(ndjson_str.as_bytes()).collect::<Result<Vec<_>, _>>()
+        } else {
+            serde_json::Deserializer::from_str(ndjson_str)
+                .into_iter::<Val>()
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| err.to_string())
+        };
+        match parsed {
+            Ok(parsed) => vals.extend(parsed),
+            Err(_) => return Val::new_err("unable to parse NDJSON"),
+        }
+        Val::new_list(vals)
+    }
+
     pub fn from_toml_str(toml_str: &str) -> Self {
         match toml::from_str::<Val>(toml_str) {
             Ok(val) => val,
@@ -412,6 +652,188 @@ impl Val {
             Err(_) => Val::new_err("unable to parse yaml"),
         }
     }
+
+    /// Infers a type that this value matches, such as `%{a: int, b: [str]}`.
+    /// The element types of a list are merged, so keys missing from some of
+    /// the maps in a list become optional.
+    pub fn infer_type(&self) -> Val {
+        match self.get_val() {
+            ValType::Null => Val::new(ValType::OptionalType(Val::new(ValType::AnyType))),
+            ValType::Int(_) => Val::new(ValType::IntType),
+            ValType::Float64(_) | ValType::Decimal(_) => Val::new(ValType::FloatType),
+            ValType::Bool(_) => Val::new(ValType::BoolType),
+            ValType::String(_) => Val::new(ValType::StringType),
+            ValType::List(vals) => {
+                let elem_type = vals
+                    .iter()
+                    .map(|val| val.infer_type())
+                    .reduce(|left, right| merge_types(&left, &right))
+                    .unwrap_or(Val::new(ValType::AnyType));
+                Val::new(ValType::ListType(elem_type))
+            }
+            ValType::Map(map) => {
+                let mut types = OrderedMap::new();
+                for (key, val) in map.get_kv_pair_slice() {
+                    types.insert(key, &val.infer_type());
+                }
+                Val::new(ValType::ObjectType(types))
+            }
+            _ => Val::new(ValType::AnyType),
+        }
+    }
+}
+
+/// The narrowest type that values of either type match. Null is inferred
+/// as `?any`, so merging it with another type makes that type optional.
+fn merge_types(left: &Val, right: &Val) -> Val {
+    match (left.get_val(), right.get_val()) {
+        (ValType::IntType, ValType::IntType)
+        | (ValType::FloatType, ValType::FloatType)
+        | (ValType::StringType, ValType::StringType)
+        | (ValType::BoolType, ValType::BoolType)
+        | (ValType::AnyType, ValType::AnyType) => left.clone(),
+        (ValType::OptionalType(left), ValType::OptionalType(right)) => {
+            optional_type(&merge_types(left, right))
+        }
+        (ValType::OptionalType(inner), _) if matches!(inner.get_val(), ValType::AnyType) => {
+            optional_type(right)
+        }
+        (_, ValType::OptionalType(inner)) if matches!(inner.get_val(), ValType::AnyType) => {
+            optional_type(left)
+        }
+        (ValType::OptionalType(left), _) => optional_type(&merge_types(left, right)),
+        (_, ValType::OptionalType(right)) => optional_type(&merge_types(left, right)),
+        (ValType::IntType, ValType::FloatType) | (ValType::FloatType, ValType::IntType) => {
+            Val::new(ValType::FloatType)
+        }
+        (ValType::ListType(left), ValType::ListType(right)) => {
+            Val::new(ValType::ListType(merge_types(left, right)))
+        }
+        (ValType::ObjectType(left), ValType::ObjectType(right)) => {
+            let mut types = OrderedMap::new();
+            for (key, left_type) in left.get_kv_pair_slice() {
+                let merged = match right.get(key) {
+                    None => optional_type(left_type),
+                    Some(right_type) => merge_types(left_type, &right_type),
+                };
+                types.insert(key, &merged);
+            }
+            for (key, right_type) in right.get_kv_pair_slice() {
+                if !left.has(key) {
+                    types.insert(key, &optional_type(right_type));
+                }
+            }
+            Val::new(ValType::ObjectType(types))
+        }
+        _ => Val::new(ValType::AnyType),
+    }
+}
+
+fn optional_type(typ: &Val) -> Val {
+    match typ.get_val() {
+        ValType::OptionalType(_) => typ.clone(),
+        _ => Val::new(ValType::OptionalType(typ.clone())),
+    }
+}
+
+const NULL_COLOR: &str = "\x1b[90m";
+const BOOL_COLOR: &str = "\x1b[33m";
+const NUMBER_COLOR: &str = "\x1b[36m";
+const STRING_COLOR: &str = "\x1b[32m";
+const KEY_COLOR: &str = "\x1b[1;34m";
+const ERR_COLOR: &str = "\x1b[1;31m";
+const TRUNCATED_COLOR: &str = "\x1b[90m";
+const RESET_COLOR: &str = "\x1b[0m";
+
+/// How `Val::write_with_options()` formats a value.
+pub struct WriteOptions {
+    pub use_indent: bool,
+    /// Color the output with ANSI escape codes.
+    pub color: bool,
+    /// Elements of a list or map after this many are replaced by a count.
+    pub max_items: Option<usize>,
+    /// Lists and maps nested deeper than this are replaced by a count.
+    pub max_depth: Option<usize>,
+}
+
+impl WriteOptions {
+    pub fn new(use_indent: bool) -> Self {
+        WriteOptions {
+            use_indent,
+            color: false,
+            max_items: None,
+            max_depth: None,
+        }
+    }
+
+    fn item_limit(&self) -> usize {
+        self.max_items.unwrap_or(usize::MAX)
+    }
+
+    fn is_too_deep(&self, depth: u64, len: usize) -> bool {
+        len > 0
+            && self
+                .max_depth
+                .is_some_and(|max_depth| depth >= max_depth as u64)
+    }
+}
+
+fn write_colored<W: std::io::Write>(
+    writer: &mut W,
+    opts: &WriteOptions,
+    color: &str,
+    write: impl FnOnce(&mut W) -> std::io::Result<usize>,
+) -> std::io::Result<usize> {
+    if opts.color {
+        writer.write_all(color.as_bytes())?;
+    }
+    write(writer)?;
+    if opts.color {
+        writer.write_all(RESET_COLOR.as_bytes())?;
+    }
+    Ok(0)
+}
+
+/// Writes the `... 9,812 more items` line that ends a truncated list or map.
+fn write_more_items(
+    writer: &mut impl std::io::Write,
+    indent: u64,
+    opts: &WriteOptions,
+    count: &str,
+) -> std::io::Result<usize> {
+    if opts.item_limit() > 0 {
+        if opts.use_indent {
+            writer.write_all(", ".as_bytes())?;
+        } else {
+            writer.write_all(",".as_bytes())?;
+        }
+    }
+    if opts.use_indent {
+        writer.write_all("\n".as_bytes())?;
+        for _ in 0..indent {
+            writer.write_all("  ".as_bytes())?;
+        }
+    }
+    write_colored(writer, opts, TRUNCATED_COLOR, |writer| {
+        writer.write(format!("... {}", count).as_bytes())
+    })
+}
+
+/// Formats a count of things with thousands separators, as in `9,812 items`.
+fn format_count(count: usize, noun: &str) -> String {
+    let digits = count.to_string();
+    let mut result = String::with_capacity(digits.len() + digits.len() / 3);
+    for (idx, digit) in digits.chars().enumerate() {
+        if idx > 0 && (digits.len() - idx).is_multiple_of(3) {
+            result.push(',');
+        }
+        result.push(digit);
+    }
+    if count == 1 {
+        format!("{} {}", result, noun)
+    } else {
+        format!("{} {}s", result, noun)
+    }
 }
 
 fn write_json_escaped_str(writer: &mut impl std::io::Write, val: &str) -> std::io::Result<usize> {
@@ -462,165 +884,89 @@ impl PartialOrd for Val {
 
 impl Ord for Val {
     fn cmp(&self, other: &Self) -> Ordering {
-        let lval = self.get_val();
-        let rval = other.get_val();
-        match lval {
-            ValType::Err(lval) => match rval {
-                ValType::Err(rval) => lval.cmp(rval),
-                ValType::Null => Ordering::Less,
-                ValType::Bool(_) => Ordering::Less,
-                ValType::Float64(_) => Ordering::Less,
-                ValType::String(_) => Ordering::Less,
-                ValType::List(_) => Ordering::Less,
-                ValType::Map(_) => Ordering::Less,
-                ValType::Bytes(_) => Ordering::Less,
-                ValType::IntType => todo!(),
-                ValType::FloatType => todo!(),
-                ValType::AnyType => todo!(),
-                ValType::StringType => todo!(),
-                ValType::BoolType => todo!(),
-                ValType::ListType(_) => todo!(),
-                ValType::ObjectType(_) => todo!(),
-                ValType::OptionalType(_) => todo!(),
-            },
-            ValType::Null => match rval {
-                ValType::Err(_) => Ordering::Greater,
-                ValType::Null => Ordering::Equal,
-                ValType::Bool(_) => Ordering::Less,
-                ValType::Float64(_) => Ordering::Less,
-                ValType::String(_) => Ordering::Less,
-                ValType::List(_) => Ordering::Less,
-                ValType::Map(_) => Ordering::Less,
-                ValType::Bytes(_) => Ordering::Less,
-                ValType::IntType => todo!(),
-                ValType::FloatType => todo!(),
-                ValType::AnyType => todo!(),
-                ValType::StringType => todo!(),
-                ValType::BoolType => todo!(),
-                ValType::ListType(_) => todo!(),
-                ValType::ObjectType(_) => todo!(),
-                ValType::OptionalType(_) => todo!(),
-            },
-            ValType::Bool(lval) => match rval {
-                ValType::Err(_) => Ordering::Greater,
-                ValType::Null => Ordering::Greater,
-                ValType::Bool(rval) => lval.cmp(rval),
-                ValType::Float64(_) => Ordering::Less,
-                ValType::String(_) => Ordering::Less,
-                ValType::List(_) => Ordering::Less,
-                ValType::Map(_) => Ordering::Less,
-                ValType::Bytes(_) => Ordering::Less,
-                ValType::IntType => todo!(),
-                ValType::FloatType => todo!(),
-                ValType::AnyType => todo!(),
-                ValType::StringType => todo!(),
-                ValType::BoolType => todo!(),
-                ValType::ListType(_) => todo!(),
-                ValType::ObjectType(_) => todo!(),
-                ValType::OptionalType(_) => todo!(),
-            },
-            ValType::Float64(lval) => match rval {
-                ValType::Err(_) => Ordering::Greater,
-                ValType::Null => Ordering::Greater,
-                ValType::Bool(_) => Ordering::Greater,
-                ValType::Float64(rval) => lval.total_cmp(rval),
-                ValType::String(_) => Ordering::Less,
-                ValType::List(_) => Ordering::Less,
-                ValType::Map(_) => Ordering::Less,
-                ValType::Bytes(_) => Ordering::Less,
-                ValType::IntType => todo!(),
-                ValType::FloatType => todo!(),
-                ValType::AnyType => todo!(),
-                ValType::StringType => todo!(),
-                ValType::BoolType => todo!(),
-                ValType::ListType(_) => todo!(),
-                ValType::ObjectType(_) => todo!(),
-                ValType::OptionalType(_) => todo!(),
-            },
-            ValType::String(lval) => match rval {
-                ValType::Err(_) => Ordering::Greater,
-                ValType::Null => Ordering::Greater,
-                ValType::Bool(_) => Ordering::Greater,
-                ValType::Float64(_) => Ordering::Greater,
-                ValType::String(rval) => lval.cmp(rval),
-                ValType::List(_) => Ordering::Less,
-                ValType::Map(_) => Ordering::Less,
-                ValType::Bytes(_) => Ordering::Less,
-                ValType::IntType => todo!(),
-                ValType::FloatType => todo!(),
-                ValType::AnyType => todo!(),
-                ValType::StringType => todo!(),
-                ValType::BoolType => todo!(),
-                ValType::ListType(_) => todo!(),
-                ValType::ObjectType(_) => todo!(),
-                ValType::OptionalType(_) => todo!(),
-            },
-            ValType::List(lval) => match rval {
-                ValType::Err(_) => Ordering::Greater,
-                ValType::Null => Ordering::Greater,
-                ValType::Bool(_) => Ordering::Greater,
-                ValType::Float64(_) => Ordering::Greater,
-                ValType::String(_) => Ordering::Greater,
-                ValType::List(rval) => list_cmp(lval, rval),
-                ValType::Map(_) => Ordering::Less,
-                ValType::Bytes(_) => Ordering::Less,
-                ValType::IntType => todo!(),
-                ValType::FloatType => todo!(),
-                ValType::AnyType => todo!(),
-                ValType::StringType => todo!(),
-                ValType::BoolType => todo!(),
-                ValType::ListType(_) => todo!(),
-                ValType::ObjectType(_) => todo!(),
-                ValType::OptionalType(_) => todo!(),
-            },
-            ValType::Map(lval) => match rval {
-                ValType::Err(_) => Ordering::Greater,
-                ValType::Null => Ordering::Greater,
-                ValType::Bool(_) => Ordering::Greater,
-                ValType::Float64(_) => Ordering::Greater,
-                ValType::String(_) => Ordering::Greater,
-                ValType::List(_) => Ordering::Greater,
-                ValType::Map(rval) => map_cmp(lval, rval),
-                ValType::Bytes(_) => Ordering::Less,
-                ValType::IntType => todo!(),
-                ValType::FloatType => todo!(),
-                ValType::AnyType => todo!(),
-                ValType::StringType => todo!(),
-                ValType::BoolType => todo!(),
-                ValType::ListType(_) => todo!(),
-                ValType::ObjectType(_) => todo!(),
-                ValType::OptionalType(_) => todo!(),
-            },
-            ValType::Bytes(lval) => match rval {
-                ValType::Err(_) => Ordering::Greater,
-                ValType::Null => Ordering::Greater,
-                ValType::Bool(_) => Ordering::Greater,
-                ValType::Float64(_) => Ordering::Greater,
-                ValType::String(_) => Ordering::Greater,
-                ValType::List(_) => Ordering::Greater,
-                ValType::Map(_) => Ordering::Greater,
-                ValType::Bytes(rval) => bytes_cmp(lval, rval),
-                ValType::IntType => todo!(),
-                ValType::FloatType => todo!(),
-                ValType::AnyType => todo!(),
-                ValType::StringType => todo!(),
-                ValType::BoolType => todo!(),
-                ValType::ListType(_) => todo!(),
-                ValType::ObjectType(_) => todo!(),
-                ValType::OptionalType(_) => todo!(),
-            },
-            ValType::IntType => todo!(),
-            ValType::FloatType => todo!(),
-            ValType::AnyType => todo!(),
-            ValType::StringType => todo!(),
-            ValType::BoolType => todo!(),
-            ValType::ListType(_) => todo!(),
-            ValType::ObjectType(_) => todo!(),
-            ValType::OptionalType(_) => todo!(),
+        match (self.get_val(), other.get_val()) {
+            (ValType::Err(lval), ValType::Err(rval)) => lval.msg.cmp(&rval.msg),
+            (ValType::Bool(lval), ValType::Bool(rval)) => lval.cmp(rval),
+            (ValType::Int(lval), ValType::Int(rval)) => lval.cmp(rval),
+            (ValType::Int(lval), ValType::Float64(rval)) => int_float_cmp(*lval, *rval),
+            (ValType::Int(lval), ValType::Decimal(rval)) => BigDecimal::from(*lval).cmp(rval),
+            (ValType::Float64(lval), ValType::Int(rval)) => int_float_cmp(*rval, *lval).reverse(),
+            (ValType::Float64(lval), ValType::Float64(rval)) => lval.total_cmp(rval),
+            (ValType::Float64(lval), ValType::Decimal(rval)) => {
+                decimal_float_cmp(rval, *lval).reverse()
+            }
+            (ValType::Decimal(lval), ValType::Int(rval)) => lval.cmp(&BigDecimal::from(*rval)),
+            (ValType::Decimal(lval), ValType::Float64(rval)) => decimal_float_cmp(lval, *rval),
+            (ValType::Decimal(lval), ValType::Decimal(rval)) => lval.cmp(rval),
+            (ValType::String(lval), ValType::String(rval)) => lval.cmp(rval),
+            (ValType::Datetime(lval, _), ValType::Datetime(rval, _)) => lval.cmp(rval),
+            (ValType::List(lval), ValType::List(rval)) => list_cmp(lval, rval),
+            (ValType::Map(lval), ValType::Map(rval))
+            | (ValType::ObjectType(lval), ValType::ObjectType(rval)) => map_cmp(lval, rval),
+            (ValType::Bytes(lval), ValType::Bytes(rval)) => bytes_cmp(lval, rval),
+            (ValType::ListType(lval), ValType::ListType(rval))
+            | (ValType::OptionalType(lval), ValType::OptionalType(rval)) => lval.cmp(rval),
+            (lval, rval) => type_rank(lval).cmp(&type_rank(rval)),
         }
     }
 }
 
+/// Position of a value's type when sorting values of different types. Numbers
+/// of every kind share a rank so that they're compared by value.
+fn type_rank(val: &ValType) -> u8 {
+    match val {
+        ValType::Err(_) => 0,
+        ValType::Null => 1,
+        ValType::Bool(_) => 2,
+        ValType::Int(_) | ValType::Float64(_) | ValType::Decimal(_) => 3,
+        ValType::String(_) => 4,
+        ValType::Datetime(..) => 5,
+        ValType::List(_) => 6,
+        ValType::Map(_) => 7,
+        ValType::Bytes(_) => 8,
+        ValType::IntType => 9,
+        ValType::FloatType => 10,
+        ValType::AnyType => 11,
+        ValType::StringType => 12,
+        ValType::BoolType => 13,
+        ValType::ListType(_) => 14,
+        ValType::ObjectType(_) => 15,
+        ValType::OptionalType(_) => 16,
+    }
+}
+
+/// Compares an integer with a float exactly, even when the integer can't be
+/// represented as a float.
+fn int_float_cmp(left: i64, right: f64) -> Ordering {
+    match (left as f64).total_cmp(&right) {
+        Ordering::Equal => (left as i128).cmp(&(right as i128)),
+        ord => ord,
+    }
+}
+
+/// Compares a decimal with a float exactly. Infinities and NaN sort like
+/// they do among floats.
+fn decimal_float_cmp(left: &BigDecimal, right: f64) -> Ordering {
+    match BigDecimal::try_from(right) {
+        Err(_) => left.to_f64().unwrap_or(0.0).total_cmp(&right),
+        // -0.0 sorts before 0 like it does among floats
+        Ok(_) if right == 0.0 && right.is_sign_negative() && left.is_zero() => Ordering::Greater,
+        Ok(right) => left.cmp(&right),
+    }
+}
+
+/// The float or integer that a decimal is exactly equal to, as a float.
+fn decimal_as_exact_f64(val: &BigDecimal) -> Option<f64> {
+    if let Some(int) = val.to_i64().filter(|_| val.is_integer()) {
+        return Some(int as f64);
+    }
+    let float = val.to_f64()?;
+    match BigDecimal::try_from(float) {
+        Ok(exact) if &exact == val => Some(float),
+        _ => None,
+    }
+}
+
 fn list_cmp(left: &Vec<Val>, right: &Vec<Val>) -> Ordering {
     for idx in 0..std::cmp::min(left.len(), right.len()) {
         let result = left[idx].cmp(&right[idx]);
@@ -850,70 +1196,79 @@ impl<'de> serde::de::Visitor<'de> for ValVisitor {
     where
         E: serde::de::Error,
     {
-        Ok(Val::new_f64(v as f64))
+        match i64::try_from(v) {
+            Ok(v) => Ok(Val::new_int(v)),
+            Err(_) => Ok(Val::new_decimal(BigDecimal::from(v))),
+        }
     }
 
     fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
     where
         E: serde::de::Error,
     {
-        Ok(Val::new_f64(v as f64))
+        Ok(Val::new_int(v))
     }
 
     fn visit_i32<E>(self, v: i32) -> Result<Self::Value, E>
     where
         E: serde::de::Error,
     {
-        Ok(Val::new_f64(v as f64))
+        Ok(Val::new_int(v as i64))
     }
 
     fn visit_i16<E>(self, v: i16) -> Result<Self::Value, E>
     where
         E: serde::de::Error,
     {
-        Ok(Val::new_f64(v as f64))
+        Ok(Val::new_int(v as i64))
     }
 
     fn visit_i8<E>(self, v: i8) -> Result<Self::Value, E>
     where
         E: serde::de::Error,
     {
-        Ok(Val::new_f64(v as f64))
+        Ok(Val::new_int(v as i64))
     }
 
     fn visit_u128<E>(self, v: u128) -> Result<Self::Value, E>
     where
         E: serde::de::Error,
     {
-        Ok(Val::new_f64(v as f64))
+        match i64::try_from(v) {
+            Ok(v) => Ok(Val::new_int(v)),
+            Err(_) => Ok(Val::new_decimal(BigDecimal::from(v))),
+        }
     }
 
     fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
     where
         E: serde::de::Error,
     {
-        Ok(Val::new_f64(v as f64))
+        match i64::try_from(v) {
+            Ok(v) => Ok(Val::new_int(v)),
+            Err(_) => Ok(Val::new_decimal(BigDecimal::from(v))),
+        }
     }
 
     fn visit_u32<E>(self, v: u32) -> Result<Self::Value, E>
     where
         E: serde::de::Error,
     {
-        Ok(Val::new_f64(v as f64))
+        Ok(Val::new_int(v as i64))
     }
 
     fn visit_u16<E>(self, v: u16) -> Result<Self::Value, E>
     where
         E: serde::de::Error,
     {
-        Ok(Val::new_f64(v as f64))
+        Ok(Val::new_int(v as i64))
     }
 
     fn visit_u8<E>(self, v: u8) -> Result<Self::Value, E>
     where
         E: serde::de::Error,
     {
-        Ok(Val::new_f64(v as f64))
+        Ok(Val::new_int(v as i64))
     }
 
     fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E>
@@ -989,7 +1344,7 @@ impl<'de> serde::de::Visitor<'de> for ValVisitor {
     where
         E: serde::de::Error,
     {
-        Ok(Val::from_json_str(v.to_string().as_str()))
+        Ok(Val::from_json_str(v.to_string().as_str(), false))
     }
 
     fn visit_enum<A>(self, _data: A) -> Result<Self::Value, A::Error>
@@ -1037,6 +1392,10 @@ impl<'de> serde::de::Deserialize<'de> for Val {
     }
 }
 
+/// Floats below this size are whole numbers exactly when `trunc()` leaves
+/// them unchanged and fit in an i64.
+pub const MAX_EXACT_FLOAT_INT: f64 = 9_007_199_254_740_992.0;
+
 impl serde::ser::Serialize for Val {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -1045,19 +1404,24 @@ impl serde::ser::Serialize for Val {
         match self.get_val() {
             ValType::Null => serializer.serialize_unit(),
             ValType::Err(err) => serializer.collect_map(
-                [(Val::new_str("ERROR"), Val::new_str(err))]
+                [(Val::new_str("ERROR"), Val::new_str(err.msg.as_str()))]
                     .iter()
                     .map(|(key, val)| (key, val)),
             ),
-            ValType::Float64(val) => {
-                if *val == val.trunc() {
-                    serializer.serialize_i64(*val as i64)
-                } else {
-                    serializer.serialize_f64(*val)
-                }
+            ValType::Int(val) => serializer.serialize_i64(*val),
+            // Whole floats are written without a fraction, like `write_to_str()`
+            ValType::Float64(val) if *val == val.trunc() && val.abs() < MAX_EXACT_FLOAT_INT => {
+                serializer.serialize_i64(*val as i64)
             }
+            ValType::Float64(val) => serializer.serialize_f64(*val),
+            // Decimals are written as floats, Val::serialize_exact() keeps every digit
+            ValType::Decimal(val) => match val.to_i64().filter(|_| val.is_integer()) {
+                Some(val) => serializer.serialize_i64(val),
+                None => serializer.serialize_f64(val.to_f64().unwrap_or(f64::NAN)),
+            },
             ValType::Bool(val) => serializer.serialize_bool(*val),
             ValType::String(val) => serializer.serialize_str(val.as_str()),
+            ValType::Datetime(val, _) => serializer.serialize_str(datetime_to_iso(val).as_str()),
             ValType::List(val) => serializer.collect_seq(val.iter()),
             ValType::Map(val) => {
                 serializer.collect_map(val.get_kv_pair_slice().iter().map(|(key, val)| (key, val)))
@@ -1075,6 +1439,138 @@ impl serde::ser::Serialize for Val {
     }
 }
 
+impl Val {
+    /// Serializes the value with a serde serializer such as
+    /// `serde_json::to_string()`, keeping every digit of the decimals in it.
+    /// serde only knows about floats, so each decimal is serialized as a
+    /// placeholder string that is replaced by its digits afterwards.
+    pub fn serialize_exact<E>(
+        &self,
+        serialize: impl FnOnce(&Val) -> Result<String, E>,
+    ) -> Result<String, E> {
+        if !has_inexact_decimal(self) {
+            return serialize(self);
+        }
+        let mut strings = Vec::<&str>::new();
+        collect_strs(self, &mut strings);
+        let mut prefix = "kj_decimal_".to_string();
+        while strings.iter().any(|text| text.contains(prefix.as_str())) {
+            prefix.push('x');
+        }
+
+        let mut decimals = Vec::<String>::new();
+        let val = replace_decimals(self, prefix.as_str(), &mut decimals);
+        let mut text = serialize(&val)?;
+        for (idx, decimal) in decimals.iter().enumerate() {
+            // Ends with `_` so that placeholder 1 isn't a prefix of placeholder 10
+            let placeholder = format!("{}{}_", prefix, idx);
+            text = text
+                .replace(format!("\"{}\"", placeholder).as_str(), decimal)
+                .replace(placeholder.as_str(), decimal);
+        }
+        Ok(text)
+    }
+}
+
+/// True for decimals that serde can't write exactly, the ones other than
+/// integers that fit in an i64.
+fn is_inexact_decimal(decimal: &BigDecimal) -> bool {
+    !decimal.is_integer() || decimal.to_i64().is_none()
+}
+
+fn has_inexact_decimal(val: &Val) -> bool {
+    match val.get_val() {
+        ValType::Decimal(decimal) => is_inexact_decimal(decimal),
+        ValType::List(vals) => vals.iter().any(has_inexact_decimal),
+        ValType::Map(map) => map
+            .get_kv_pair_slice()
+            .iter()
+            .any(|(_, val)| has_inexact_decimal(val)),
+        _ => false,
+    }
+}
+
+fn collect_strs<'a>(val: &'a Val, strings: &mut Vec<&'a str>) {
+    match val.get_val() {
+        ValType::String(text) => strings.push(text.as_str()),
+        ValType::Err(err) => strings.push(err.msg.as_str()),
+        ValType::List(vals) => vals.iter().for_each(|val| collect_strs(val, strings)),
+        ValType::Map(map) => {
+            for (key, val) in map.get_kv_pair_slice() {
+                collect_strs(key, strings);
+                collect_strs(val, strings);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Replaces the inexact decimals, other than in map keys, with placeholder
+/// strings and adds their digits to `decimals`.
+fn replace_decimals(val: &Val, prefix: &str, decimals: &mut Vec<String>) -> Val {
+    match val.get_val() {
+        ValType::Decimal(decimal) if is_inexact_decimal(decimal) => {
+            let placeholder = format!("{}{}_", prefix, decimals.len());
+            decimals.push(decimal.to_plain_string());
+            Val::new_str(placeholder.as_str())
+        }
+        ValType::List(vals) => Val::new_list(
+            vals.iter()
+                .map(|val| replace_decimals(val, prefix, decimals))
+                .collect(),
+        ),
+        ValType::Map(map) => {
+            let mut result = OrderedMap::new();
+            for (key, val) in map.get_kv_pair_slice() {
+                result.insert(key, &replace_decimals(val, prefix, decimals));
+            }
+            Val::new_map(result)
+        }
+        _ => val.clone(),
+    }
+}
+
+/// A timezone with daylight saving rules, kept on datetimes converted with
+/// to_timezone() so that arithmetic and floor_time() use the offset in effect
+/// at the result rather than the one at the original time.
+#[derive(Clone, Copy)]
+pub enum Zone {
+    Named(Tz),
+    Local,
+}
+
+impl Zone {
+    /// Converts a datetime to the offset this zone has at that point in time.
+    pub fn convert(&self, val: &DateTime<FixedOffset>) -> DateTime<FixedOffset> {
+        match self {
+            Zone::Named(tz) => val.with_timezone(tz).fixed_offset(),
+            Zone::Local => val.with_timezone(&Local).fixed_offset(),
+        }
+    }
+
+    /// Finds the datetime with a local time in this zone, taking the earlier
+    /// one when clocks go back. Returns None for times skipped when clocks go
+    /// forward.
+    pub fn at_local(&self, val: &NaiveDateTime) -> Option<DateTime<FixedOffset>> {
+        match self {
+            Zone::Named(tz) => tz
+                .from_local_datetime(val)
+                .earliest()
+                .map(|val| val.fixed_offset()),
+            Zone::Local => Local
+                .from_local_datetime(val)
+                .earliest()
+                .map(|val| val.fixed_offset()),
+        }
+    }
+}
+
+/// Formats a datetime as ISO 8601, with `Z` for UTC and only as many
+/// fractional digits as needed.
+pub fn datetime_to_iso(val: &DateTime<FixedOffset>) -> String {
+    val.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
 fn serialize_as_str<S>(val: &Val, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
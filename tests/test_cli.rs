@@ -0,0 +1,484 @@
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    struct CliOutput {
+        status: i32,
+        stdout: String,
+        stderr: String,
+    }
+
+    fn run_kj(args: &[&str], stdin: &str) -> CliOutput {
+        run_kj_with_env(args, &[], stdin)
+    }
+
+    fn run_kj_with_env(args: &[&str], env: &[(&str, &str)], stdin: &str) -> CliOutput {
+        // Keep the developer's own init file out of the tests
+        let mut proc = Command::new(env!("CARGO_BIN_EXE_kilojoule"))
+            .args(args)
+            .env("XDG_CONFIG_HOME", "/nonexistent")
+            .envs(env.iter().copied())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        proc.stdin
+            .take()
+            .unwrap()
+            .write_all(stdin.as_bytes())
+            .unwrap();
+        let output = proc.wait_with_output().unwrap();
+
+        CliOutput {
+            status: output.status.code().unwrap(),
+            stdout: String::from_utf8(output.stdout).unwrap(),
+            stderr: String::from_utf8(output.stderr).unwrap(),
+        }
+    }
+
+    fn temp_file(name: &str, contents: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("kj_test_cli_{}", std::process::id()));
+        let path = dir.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_query_argument() {
+        let out = run_kj(&["[1, 2] | map(. + 1)"], "");
+        assert_eq!(out.status, 0);
+        assert_eq!(out.stdout, "[\n  2, \n  3\n]\n");
+
+        let out = run_kj(&["-c", "{a: [1, 2]}"], "");
+        assert_eq!(out.stdout, "{\"a\":[1,2]}\n");
+
+        let out = run_kj(&["--raw", "'a\\tb'"], "");
+        assert_eq!(out.stdout, "a\tb\n");
+
+        let out = run_kj(&["inj() | .a"], "{\"a\": 42}");
+        assert_eq!(out.stdout, "42\n");
+    }
+
+    #[test]
+    fn test_query_file_and_input_files() {
+        let query = temp_file("query.kj", ".name");
+        let a = temp_file("a.json", r#"{"name": "a"}"#);
+        let b = temp_file("b.json", r#"{"name": "b"}"#);
+
+        let out = run_kj(&["-c", "-f", query.as_str(), a.as_str(), b.as_str()], "");
+        assert_eq!(out.status, 0);
+        assert_eq!(out.stdout, "\"a\"\n\"b\"\n");
+
+        let out = run_kj(&["-c", ".name", a.as_str(), "-"], r#"{"name": "stdin"}"#);
+        assert_eq!(out.stdout, "\"a\"\n\"stdin\"\n");
+
+        let out = run_kj(&["-c", "-f", "/nonexistent/query.kj"], "");
+        assert_eq!(out.status, 2);
+        assert!(out.stderr.contains("/nonexistent/query.kj"));
+
+        let out = run_kj(&["-c", ".", "/nonexistent/input.json"], "");
+        assert_eq!(out.status, 2);
+        assert!(out.stderr.contains("/nonexistent/input.json"));
+    }
+
+    #[test]
+    fn test_terminal_output_options() {
+        let out = run_kj(&["--color", "always", "-c", "{a: [1, null, \"x\"]}"], "");
+        assert_eq!(
+            out.stdout,
+            "{\x1b[1;34m\"a\"\x1b[0m:[\x1b[36m1\x1b[0m,\x1b[90mnull\x1b[0m,\x1b[32m\"x\"\x1b[0m]}\n"
+        );
+
+        // Piped output is exact unless truncation is asked for
+        let out = run_kj(&["-c", "range(10000)"], "");
+        assert!(out.stdout.ends_with(",9998,9999]\n"));
+        assert!(!out.stdout.contains('\x1b'));
+
+        let out = run_kj(&["--max-items", "2", "range(10000)"], "");
+        assert_eq!(out.stdout, "[\n  0, \n  1, \n  ... 9,998 more items\n]\n");
+
+        let out = run_kj(
+            &["-c", "--max-depth", "1", "{a: [1], b: {c: 1}, d: []}"],
+            "",
+        );
+        assert_eq!(
+            out.stdout,
+            "{\"a\":[... 1 item],\"b\":{... 1 key},\"d\":[]}\n"
+        );
+
+        let out = run_kj(&["--color", "sometimes", "1"], "");
+        assert_eq!(out.status, 2);
+        assert!(out
+            .stderr
+            .starts_with("kj: --color requires auto, always or never"));
+    }
+
+    #[test]
+    fn test_output_formats() {
+        let query = "[{a: 1, b: \"x\"}, {a: 2, b: \"y\"}]";
+        let out = run_kj(&["-o", "yaml", query], "");
+        assert_eq!(out.stdout, "- a: 1\n  b: x\n- a: 2\n  b: y\n");
+
+        let out = run_kj(&["-o", "toml", "{a: {b: 1}}"], "");
+        assert_eq!(out.stdout, "[a]\nb = 1\n");
+
+        let out = run_kj(&["-o", "csv", "[[1, \"a,b\"], [2, 3]]"], "");
+        assert_eq!(out.stdout, "1,\"a,b\"\n2,3\n");
+
+        let out = run_kj(&["-o", "tsv", "[[1, 2], [3, 4]]"], "");
+        assert_eq!(out.stdout, "1\t2\n3\t4\n");
+
+        let out = run_kj(&["-o", "table", query], "");
+        assert_eq!(out.stdout, "a  b\n1  x\n2  y\n");
+
+        let out = run_kj(&["-o", "ndjson", query], "");
+        assert_eq!(out.stdout, "{\"a\":1,\"b\":\"x\"}\n{\"a\":2,\"b\":\"y\"}\n");
+
+        let out = run_kj(&["-o", "raw", "\"hello\""], "");
+        assert_eq!(out.stdout, "hello\n");
+        let out = run_kj(&["-r", "\"a\\n\""], "");
+        assert_eq!(out.stdout, "a\n\n");
+        let out = run_kj(&["-o", "json", "\"hello\""], "");
+        assert_eq!(out.stdout, "\"hello\"\n");
+
+        let out = run_kj(&["-o", "csv", "1"], "");
+        assert_eq!(out.status, 2);
+        assert_eq!(
+            out.stderr,
+            "kj: unable to write the output: to_csv() must be called on a list\n"
+        );
+
+        let out = run_kj(&["-o", "xml", "1"], "");
+        assert_eq!(out.status, 2);
+        assert!(out.stderr.starts_with("kj: -o requires json, yaml"));
+    }
+
+    #[test]
+    fn test_decimal() {
+        let out = run_kj(&["--decimal", "0.1 + 0.2"], "");
+        assert_eq!(out.stdout, "0.3\n");
+        let out = run_kj(&["0.1 + 0.2"], "");
+        assert_eq!(out.stdout, "0.30000000000000004\n");
+        let out = run_kj(
+            &["--decimal", "-c", "[1.5 ** 3, 2 ** 100, 1.5 ** 100000000]"],
+            "",
+        );
+        assert_eq!(out.stdout, "[3.375,1267650600228229401496703205376,null]\n");
+        let out = run_kj(
+            &["--decimal", "-c", "inj() | [min(), max()]"],
+            "[0.30, 0.1, 123456789012345678901234567890]",
+        );
+        assert_eq!(out.stdout, "[0.1,123456789012345678901234567890]\n");
+
+        let out = run_kj(
+            &[
+                "--decimal",
+                "-c",
+                "inj() | [.price, .price * 3, .qty + 1, to_json()]",
+            ],
+            "{\"price\": 19.990, \"qty\": 123456789012345678901234567890}",
+        );
+        assert_eq!(
+            out.stdout,
+            "[19.990,59.970,123456789012345678901234567891,\"{\\\"price\\\":19.990,\\\"qty\\\":123456789012345678901234567890}\"]\n"
+        );
+
+        let out = run_kj(
+            &[
+                "--decimal",
+                "-c",
+                "'a\\n1.10\\n2.20' | from_csv(:header true, :infer_types true) | [.[0].a + .[1].a, [.[0].a, .[1].a] | sum()]",
+            ],
+            "",
+        );
+        assert_eq!(out.stdout, "[3.30,3.30]\n");
+
+        let out = run_kj(
+            &["--decimal", "--ndjson", "-c", "."],
+            "1.50 {\"a\": [2.000, \"\\u00e9\\ud83d\\ude00\"]}\n-1e3 true null\n",
+        );
+        assert_eq!(
+            out.stdout,
+            "1.50\n{\"a\":[2.000,\"é😀\"]}\n-1000\ntrue\nnull\n"
+        );
+
+        let out = run_kj(&["--decimal", "--ndjson", "."], "[1, 01]");
+        assert_eq!(out.status, 2);
+        assert_eq!(
+            out.stderr,
+            "kj: unable to parse JSON from stdin: invalid number at line 1 column 7\n"
+        );
+
+        let input = "{\"price\": 19.990, \"items\": [1.10, {\"x\": \"kj_decimal_0_\"}]}";
+        let out = run_kj(&["--decimal", "-o", "yaml", "inj()"], input);
+        assert_eq!(
+            out.stdout,
+            "price: 19.990\nitems:\n- 1.10\n- x: kj_decimal_0_\n"
+        );
+        let out = run_kj(&["--decimal", "-o", "toml", "inj()"], input);
+        assert_eq!(
+            out.stdout,
+            "price = 19.990\nitems = [1.10, { x = \"kj_decimal_0_\" }]\n"
+        );
+        let out = run_kj(
+            &["--decimal", "-o", "ndjson", "inj() | [.price, .items]"],
+            input,
+        );
+        assert_eq!(out.stdout, "19.990\n[1.10,{\"x\":\"kj_decimal_0_\"}]\n");
+
+        let out = run_kj(&["--decimal", "-c", "'[1.0] 2' | from_json()"], "");
+        assert_eq!(out.stdout, "{\"ERROR\":\"unable to parse JSON\"}\n");
+    }
+
+    #[test]
+    fn test_exit_status() {
+        let out = run_kj(&["1 / 0"], "");
+        assert_eq!(out.status, 0);
+
+        let out = run_kj(&["-e", "1 / 0"], "");
+        assert_eq!(out.status, 1);
+        assert_eq!(out.stdout, "{\"ERROR\":\"divide by zero\"}\n");
+
+        let out = run_kj(&["--exit-status", "1 / 1"], "");
+        assert_eq!(out.status, 0);
+
+        let out = run_kj(&["--bad-flag", "1"], "");
+        assert_eq!(out.status, 2);
+        assert!(out.stderr.contains("--bad-flag"));
+    }
+
+    #[test]
+    fn test_variable_arguments() {
+        let out = run_kj(&["-c", "--arg", "name", "kj", "{name: name}"], "");
+        assert_eq!(out.stdout, "{\"name\":\"kj\"}\n");
+
+        let out = run_kj(&["-c", "--argjson", "vals", "[1, 2]", "vals | len()"], "");
+        assert_eq!(out.stdout, "2\n");
+
+        let config = temp_file("config.json", r#"{"limit": 3}"#);
+        let out = run_kj(
+            &["-c", "--argfile", "config", config.as_str(), "config.limit"],
+            "",
+        );
+        assert_eq!(out.stdout, "3\n");
+
+        let out = run_kj(&["--argjson", "vals", "[1,", "vals"], "");
+        assert_eq!(out.status, 2);
+        assert!(out.stderr.contains("vals"));
+
+        let out = run_kj(&["-c", "--arg", "x", "--decimal", "[x, 0.1 + 0.2]"], "");
+        assert_eq!(out.stdout, "[\"--decimal\",0.30000000000000004]\n");
+
+        let out = run_kj(&["-c", "--argjson", "x", "0.10", "--decimal", "x"], "");
+        assert_eq!(out.stdout, "0.10\n");
+    }
+
+    #[test]
+    fn test_ndjson_stream() {
+        let out = run_kj(
+            &["--ndjson", ".a"],
+            "{\"a\": 1}\n{\"a\": [2]}\n\n{\"a\": \"x\"} {\"a\": 4}",
+        );
+        assert_eq!(out.status, 0);
+        assert_eq!(out.stdout, "1\n[2]\n\"x\"\n4\n");
+
+        let out = run_kj(
+            &["--ndjson", "-r", ".a"],
+            "{\"a\": \"x\"}\n{\"a\": \"y\"}\n",
+        );
+        assert_eq!(out.stdout, "x\ny\n");
+
+        let out = run_kj(&["--ndjson", ".", "-"], "1 2\n");
+        assert_eq!(out.stdout, "1\n2\n");
+
+        let out = run_kj(&["--ndjson", "."], "1\n{\"a\":\n");
+        assert_eq!(out.status, 2);
+        assert_eq!(out.stdout, "1\n");
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let out = run_kj(&["[1, 2] | map(. + 1"], "");
+        assert_eq!(out.status, 3);
+        assert_eq!(out.stdout, "");
+        assert_eq!(
+            out.stderr,
+            "Parse error at line 1, column 19: expected ')' to close call to map( opened here\n  \
+             |\n\
+             1 | [1, 2] | map(. + 1\n  \
+             |          ----     ^\n"
+        );
+    }
+
+    #[test]
+    fn test_eval_error_location() {
+        let out = run_kj(&["-c", "[[1, \"a\"]] | map(map(. * 2)) | .[0][1]"], "");
+        assert_eq!(out.status, 0);
+        assert_eq!(
+            out.stdout,
+            "{\"ERROR\":\"Left side of multiplication has to be a number\"}\n"
+        );
+        assert_eq!(
+            out.stderr,
+            "Error at line 1, column 22: Left side of multiplication has to be a number\n  \
+             |\n\
+             1 | [[1, \"a\"]] | map(map(. * 2)) | .[0][1]\n  \
+             |                      ^^^^^\n\
+             in map -> map -> *\n"
+        );
+
+        let out = run_kj(&["-e", "-f", "/dev/stdin"], "let x = 1\n\n| x | nope()");
+        assert_eq!(out.status, 1);
+        assert!(out
+            .stderr
+            .starts_with("Error at line 3, column 7: Unknown function"));
+    }
+
+    #[test]
+    fn test_recursion_limit() {
+        let out = run_kj(&["-c", "def f() = f(); f()"], "");
+        assert_eq!(out.status, 0);
+        assert_eq!(
+            out.stdout,
+            "{\"ERROR\":\"maximum recursion depth exceeded\"}\n"
+        );
+        assert!(out
+            .stderr
+            .ends_with("in f -> f -> f -> f -> f -> ... 991 more -> f -> f -> f -> f -> f\n"));
+
+        let query = "def f(n) = if(n == 0, 0, f(n - 1)); [f(400), f(5000)]";
+        let out = run_kj(&["-c", query], "");
+        assert_eq!(
+            out.stdout,
+            "[0,{\"ERROR\":\"maximum recursion depth exceeded\"}]\n"
+        );
+    }
+
+    #[test]
+    fn test_imports() {
+        temp_file(
+            "import/lib/k8s.kj",
+            "include \"util.kj\";\n\
+             def names() = map(.name);\n\
+             def fact(n) = if(n <= 1, 1, n * fact(n - 1));\n",
+        );
+        temp_file("import/lib/util.kj", "def shout() = upper();\n");
+        let query = temp_file(
+            "import/query.kj",
+            "import \"lib/k8s.kj\" as k8s;\n\
+             [{name: \"a\"}] | k8s::names() | [., k8s::fact(4), \"x\" | k8s::shout()]",
+        );
+        let out = run_kj(&["-c", "-f", query.as_str()], "");
+        assert_eq!(out.stdout, "[[\"a\"],24,\"X\"]\n");
+
+        let lib_dir = std::path::Path::new(query.as_str())
+            .parent()
+            .unwrap()
+            .join("lib");
+        let out = run_kj_with_env(
+            &["-c", "import \"util.kj\" as u; \"x\" | u::shout()"],
+            &[("KJ_PATH", lib_dir.to_str().unwrap())],
+            "",
+        );
+        assert_eq!(out.stdout, "\"X\"\n");
+
+        let out = run_kj(&["-e", "import \"missing/lib.kj\" as m; 1"], "");
+        assert_eq!(out.status, 1);
+        assert!(out.stderr.contains("unable to find \"missing/lib.kj\""));
+
+        temp_file("import/a.kj", "import \"b.kj\" as b;");
+        temp_file("import/b.kj", "import \"a.kj\" as a;");
+        let query = temp_file("import/cycle.kj", "import \"a.kj\" as a; 1");
+        let out = run_kj(&["-e", "-f", query.as_str()], "");
+        assert_eq!(out.status, 1);
+        assert!(out.stderr.contains("import cycle: "));
+        assert!(out.stderr.contains("a.kj -> "));
+    }
+
+    #[test]
+    fn test_init_file() {
+        let init = temp_file(
+            "config/kj/init.kj",
+            "let team = \"sre\" |\ndef twice() = . * 2;\n",
+        );
+        let config_dir = init.strip_suffix("/kj/init.kj").unwrap();
+        let env = [("XDG_CONFIG_HOME", config_dir)];
+
+        let out = run_kj_with_env(&["-c", "[team, 4 | twice()]"], &env, "");
+        assert_eq!(out.stdout, "[\"sre\",8]\n");
+
+        let out = run_kj_with_env(&["-c", "--no-init", "4 | twice()"], &env, "");
+        assert_eq!(
+            out.stdout,
+            "{\"ERROR\":\"Unknown function \\\"twice\\\"\"}\n"
+        );
+
+        temp_file("bad_config/kj/init.kj", "def (");
+        let bad_config_dir = config_dir.replace("/config", "/bad_config");
+        let out = run_kj_with_env(&["1"], &[("XDG_CONFIG_HOME", bad_config_dir.as_str())], "");
+        assert_eq!(out.status, 2);
+        assert!(out.stderr.contains("init.kj"));
+    }
+
+    #[test]
+    fn test_repl_history_and_multiline() {
+        let history = temp_file("data/kj/history", "");
+        std::fs::remove_file(&history).unwrap();
+        let data_dir = history.strip_suffix("/kj/history").unwrap();
+        let env = [("XDG_DATA_HOME", data_dir)];
+
+        let out = run_kj_with_env(&[], &env, "[1,\n 2] | map(\n. + 1)\n\"a\nb\"\n");
+        assert_eq!(out.status, 0);
+        assert_eq!(out.stdout, "[\n  2, \n  3\n]\n\"a\\nb\"\n");
+        assert_eq!(
+            std::fs::read_to_string(&history).unwrap(),
+            "#V2\n[1,\\n 2] | map(\\n. + 1)\n\"a\\nb\"\n"
+        );
+
+        run_kj_with_env(&[], &env, "42\n");
+        let history = std::fs::read_to_string(&history).unwrap();
+        assert!(history.ends_with("\"a\\nb\"\n42\n"));
+    }
+
+    #[test]
+    fn test_repl_commands() {
+        let lib = temp_file(
+            "repl/lib.kj",
+            "let base = 10 | def add(x, :by 1) = . + x * by;\n",
+        );
+        let saved = temp_file("repl/out.json", "");
+        let data_dir = temp_file("repl/data/.keep", "");
+        let env = [("XDG_DATA_HOME", data_dir.strip_suffix("/.keep").unwrap())];
+
+        let input = format!(
+            "[{{\"a\": 1, \"b\": \"x\"}}, {{\"a\": 2.5}}]\n:type\n:load {}\n:vars\n:help add\n1 | add(2)\n:undo\n:save {}\n:reset\n:vars\n:help nope\n",
+            lib, saved
+        );
+        let out = run_kj_with_env(&[], &env, input.as_str());
+        assert_eq!(out.status, 0);
+        let lines = out.stdout.lines().collect::<Vec<_>>();
+        assert!(lines.contains(&"%[{\"a\": float, \"b\": ?str}]"));
+        assert!(lines.contains(&"base = 10"));
+        assert!(lines.contains(&"def add(x, :by)"));
+        assert!(lines.contains(&"3"));
+        assert_eq!(
+            lines.iter().filter(|line| line.starts_with("base")).count(),
+            1
+        );
+        assert_eq!(out.stderr, "Error: unknown function \"nope\"\n");
+        assert_eq!(
+            std::fs::read_to_string(&saved).unwrap(),
+            "[\n  {\n    \"a\": 1, \n    \"b\": \"x\"\n  }, \n  {\n    \"a\": 2.5\n  }\n]\n"
+        );
+
+        let out = run_kj_with_env(&[], &env, ":time 1 + 1\n:bogus\n");
+        assert_eq!(out.stdout, "2\n");
+        assert!(out.stderr.starts_with("time: "));
+        assert!(out
+            .stderr
+            .ends_with("Error: unknown command \":bogus\", see :help\n"));
+    }
+}
@@ -33,6 +33,14 @@ mod tests {
         assert_eq!(parse_and_eval_json(expr), value);
     }
 
+    /// Writes a file called `name` that isn't valid UTF-8, the bytes "//5B" in
+    /// base64, and returns its path.
+    fn non_utf8_file(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("kj_test_{}_{}", name, std::process::id()));
+        std::fs::write(&path, [0xff, 0xfe, b'A']).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
     #[test]
     fn basic_functionality() {
         assert_json("123", json!(123));
@@ -74,6 +82,188 @@ mod tests {
     fn test_number_literals() {
         assert_json("1", json!(1));
         assert_json("1.5", json!(1.5));
+        assert_json("1.05", json!(1.05));
+        assert_json("1234567890123456789", json!(1234567890123456789i64));
+    }
+
+    #[test]
+    fn test_integers() {
+        assert_json(
+            r#" '\{"id": 1234567890123456789\}' | from_json() | [.id, .id + 1, to_json()] "#,
+            json!([
+                1234567890123456789i64,
+                1234567890123456790i64,
+                "{\"id\":1234567890123456789}"
+            ]),
+        );
+        assert_json(
+            "[7 / 2, 6 / 2, 2 * 3, 3 - 1.5, -5]",
+            json!([3.5, 3, 6, 1.5, -5]),
+        );
+        assert_json(
+            r#" '[18446744073709551615]' | from_json() | "{[.[0] + 1, -9223372036854775809, 100000000000000000000 - 1]}" "#,
+            json!("[18446744073709551616,-9223372036854775809,99999999999999999999]"),
+        );
+        // Overflowing integers become floats
+        assert_json("9223372036854775807 + 1 > 9223372036854775807", json!(true));
+        assert_json(
+            "[1 == 1.0, 1 < 1.5, [1, 1.0] | unique()]",
+            json!([true, true, [1]]),
+        );
+        assert_json(
+            "[[1, 2] | sum(), [1, 2.5] | sum(), [3, 1.5] | min(), '12' | from_num()]",
+            json!([3, 3.5, 1.5, 12]),
+        );
+        assert_json("[1, 2.5] | map(matches_type(%int))", json!([true, false]));
+    }
+
+    #[test]
+    fn test_round() {
+        assert_json(
+            "[2.675 | round(2), 2.5 | round(), -2.5 | round(), 1234 | round(-2), 7 | round(1)]",
+            json!([2.68, 3, -3, 1200, 7]),
+        );
+        assert_json(
+            r#"[2.5, 3.5, -2.5] | map(round(:mode "half_even"))"#,
+            json!([2, 4, -2]),
+        );
+        assert_json(
+            r#"[1.5 | round(:mode "down"), 1.5 | round(:mode "floor"), -1.5 | round(:mode "floor"), 1.1 | round(:mode "ceiling")]"#,
+            json!([1, 1, -2, 2]),
+        );
+        assert_json(
+            r#"[('0.1' | decimal()) + (0.2 | decimal()), 0.125 | decimal() | round(2)]"#,
+            json!([0.3, 0.13]),
+        );
+        assert_json(
+            "[1, 2.5, 2] | map(decimal()) | [min(), max()]",
+            json!([1, 2.5]),
+        );
+        assert_json(
+            r#"1 | round(:mode "sideways")"#,
+            json!({"ERROR": ":mode keyword argument in round() must be half_up, half_even, half_down, up, down, ceiling or floor"}),
+        );
+        assert_json(
+            "'a' | round()",
+            json!({"ERROR": "round() must be called on a number"}),
+        );
+        assert_json(
+            "[2 | round(1000), 2 | round(1000000000), 2 | round(:digits -1000000000)]",
+            json!([
+                2,
+                {"ERROR": "round() digits must be from -1000 to 1000"},
+                {"ERROR": "round() digits must be from -1000 to 1000"}
+            ]),
+        );
+    }
+
+    #[test]
+    fn test_math() {
+        assert_json(
+            "[2.5 | floor(), -2.5 | floor(), 2.1 | ceil(), 7 | ceil(), 3.14159 | round(:digits 2)]",
+            json!([2, -3, 3, 7, 3.14]),
+        );
+        assert_json(
+            "[16 | sqrt(), 2 | pow(10), 8 | log(2), 100 | log10(), e() | ln(), 0 | exp(), 0 | sin(), pi() | cos(), 1 | atan2(0)]",
+            json!([4, 1024, 3, 2, 1, 1, 0, -1, 1.5707963267948966]),
+        );
+        assert_json("[15, -3, 5.5] | map(clamp(0, 10))", json!([10, 0, 5.5]));
+        assert_json("[-3, 0, 2.5, -0.0] | map(sign())", json!([-1, 0, 1, 0]));
+        assert_json(
+            "[-1 | sqrt() | is_nan(), -1 | sqrt() | clamp(0, 1) | is_nan(), 0 | log10() | is_finite(), 0 | log10() < 0, 1 | is_finite(), 'x' | is_nan()]",
+            json!([true, true, false, true, true, false]),
+        );
+        assert_json(
+            "\"{[-1 | sqrt(), 0 | log10(), 0 | log10() * -1, 1.5]}\"",
+            json!("[null,null,null,1.5]"),
+        );
+        assert_json(
+            "1 | clamp(5, 0)",
+            json!({"ERROR": "clamp() lower bound must not be greater than the upper bound"}),
+        );
+        assert_json(
+            "'x' | sqrt()",
+            json!({"ERROR": "sqrt() must be called on a number"}),
+        );
+        assert_json(
+            "[2 | pow('x'), 'x' | pow(2), 0 | pow(-1), 2 | pow(0.5)]",
+            json!([
+                {"ERROR": "pow() exponent must be a number"},
+                {"ERROR": "pow() must be called on a number"},
+                {"ERROR": "divide by zero"},
+                1.4142135623730951
+            ]),
+        );
+    }
+
+    #[test]
+    fn test_statistics() {
+        assert_json(
+            "[4, 1, 3, 2] | [mean(), median(), percentile(90), percentile(50), quantiles(4)]",
+            json!([2.5, 2.5, 3.7, 2.5, [1.75, 2.5, 3.25]]),
+        );
+        assert_json(
+            "[4, 1, 3, 2] | [quantiles(1000000000), quantiles(1), quantiles(10000) | len()]",
+            json!([
+                {"ERROR": "quantiles() n must be an integer from 2 to 10000"},
+                {"ERROR": "quantiles() n must be an integer from 2 to 10000"},
+                9999
+            ]),
+        );
+        assert_json(
+            "[1, 2, 3, 4] | [variance(), variance(:sample true), stddev() | round(:digits 4)]",
+            json!([1.25, 1.6666666666666667, 1.118]),
+        );
+        assert_json(
+            "[1, 3, 3, 5] | [mean(), median(), mode()]",
+            json!([3, 3, 3]),
+        );
+        assert_json(
+            "[1, 3, 3, 7] | histogram(:bins 3)",
+            json!([
+                {"start": 1, "end": 3, "count": 1},
+                {"start": 3, "end": 5, "count": 2},
+                {"start": 5, "end": 7, "count": 1}
+            ]),
+        );
+        assert_json(
+            "[{n: 'a', v: 2}, {n: 'b', v: 4}, {n: 'a', v: 9}] | [mean(.v), median(.v), percentile(100, .v), count_by(.n), mode(.n)]",
+            json!([5, 4, 9, {"a": 2, "b": 1}, "a"]),
+        );
+        assert_json(
+            "[[] | mean(), [] | median(), [] | mode(), [] | histogram(), [] | count_by()]",
+            json!([null, null, null, [], {}]),
+        );
+        assert_json(
+            "[[1, 2] | histogram(:bins 1000000000000), [1, 2] | histogram(:bins 0)]",
+            json!([
+                {"ERROR": ":bins keyword argument in histogram() must be an integer from 1 to 10000"},
+                {"ERROR": ":bins keyword argument in histogram() must be an integer from 1 to 10000"}
+            ]),
+        );
+        assert_json(
+            "[[1, -1 | sqrt()] | histogram(), [1, 0 | log10()] | histogram()]",
+            json!([
+                {"ERROR": "histogram() can't be called on NaN or infinity"},
+                {"ERROR": "histogram() can't be called on NaN or infinity"}
+            ]),
+        );
+        assert_json(
+            "['a'] | mean()",
+            json!({"ERROR": "mean() can only be called on a list of numbers"}),
+        );
+        assert_json(
+            "[1] | percentile(101)",
+            json!({"ERROR": "percentile() p must be a number from 0 to 100"}),
+        );
+        assert_json(
+            "[1] | mean(1, 2)",
+            json!({"ERROR": "mean() must be called with 0 or 1 arguments"}),
+        );
+        assert_json(
+            "[1] | percentile()",
+            json!({"ERROR": "percentile() must be called with 1 or 2 arguments"}),
+        );
     }
 
     #[test]
@@ -295,7 +485,7 @@ mod tests {
         assert_json("not 1 < 2 or false", json!(false));
         assert_json(
             "not",
-            json!({"ERROR": "Parser error 'NoExprAfterUnaryOperator' at index 3"}),
+            json!({"ERROR": "Parse error at line 1, column 4: expected an expression after the unary operator"}),
         );
     }
 
@@ -316,6 +506,25 @@ mod tests {
         assert_json("1/0", json!({"ERROR": "divide by zero"}));
     }
 
+    #[test]
+    fn test_modulo_floor_divide_and_power() {
+        assert_json("[125, -1, 60] | map(. % 60)", json!([5, 59, 0]));
+        assert_json("[5.5 % 2, -5.5 % 2, 7 % -3]", json!([1.5, 0.5, -2]));
+        assert_json("[7 // 2, -7 // 2, 7.5 // 2]", json!([3, -4, 3]));
+        assert_json("[2 ** 10, 2 ** -1, 4 ** 0.5]", json!([1024, 0.5, 2]));
+        assert_json("2 ** 3 ** 2", json!(512));
+        assert_json("-2 ** 2", json!(-4));
+        assert_json("1 + 2 * 3 ** 2 - 10 % 4", json!(17));
+        assert_json("2 ** 64 > 9223372036854775807", json!(true));
+        assert_json("1 % 0", json!({"ERROR": "divide by zero"}));
+        assert_json("1 // 0.0", json!({"ERROR": "divide by zero"}));
+        assert_json("0 ** -1", json!({"ERROR": "divide by zero"}));
+        assert_json(
+            "'a' % 2",
+            json!({"ERROR": "Left side of modulo has to be a number"}),
+        );
+    }
+
     #[test]
     fn test_negative() {
         assert_json("-100", json!(-100));
@@ -334,12 +543,17 @@ mod tests {
     fn test_min() {
         assert_json("[1,2,3,4] | min()", json!(1));
         assert_json("[] | min()", json!(null));
+        assert_json("[18446744073709551615, 1, -2.5] | min()", json!(-2.5));
     }
 
     #[test]
     fn test_max() {
         assert_json("[1,2,3,4] | max()", json!(4));
         assert_json("[] | max()", json!(null));
+        assert_json(
+            "[18446744073709551615, 1, 2.5] | max()",
+            json!(18446744073709551615u64),
+        );
     }
 
     #[test]
@@ -469,6 +683,171 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_regex() {
+        assert_json(
+            r#"['abc123', 'xyz'] | map(test('[0-9]+'))"#,
+            json!([true, false]),
+        );
+        assert_json(
+            r#"['Hello' | test('hello'), 'Hello' | test('hello', :flags 'i')]"#,
+            json!([false, true]),
+        );
+        assert_json(
+            r#"'héllo 42' | match('(?P<word>\\w+) (\\d+)(x)?')"#,
+            json!({
+                "offset": 0,
+                "length": 8,
+                "string": "héllo 42",
+                "groups": ["héllo", "42", null],
+                "captures": {"word": "héllo"}
+            }),
+        );
+        assert_json(r#"'é42' | match('\\d+') | .offset"#, json!(1));
+        assert_json(r#"'abc' | match('\\d+')"#, json!(null));
+        assert_json(
+            r#"'a1 b22 c333' | [scan('\\d+'), scan('(\\w)(\\d+)')]"#,
+            json!([["1", "22", "333"], [["a", "1"], ["b", "22"], ["c", "333"]]]),
+        );
+        assert_json(
+            r#"'one\ntwo' | scan('^\\w', :flags 'm')"#,
+            json!(["o", "t"]),
+        );
+        assert_json(
+            r#"'a-b-c' | [sub('-', '+'), gsub('-', '+'), gsub('(?P<l>\\w)', '$l$l')]"#,
+            json!(["a+b-c", "a+b+c", "aa-bb-cc"]),
+        );
+        assert_json(
+            r#"'one, two,three' | split_re(',\\s*')"#,
+            json!(["one", "two", "three"]),
+        );
+        assert_json(
+            r#"'a' | test('(')"#,
+            json!({"ERROR": "Invalid regex in test(): regex parse error:\n    (\n    ^\nerror: unclosed group"}),
+        );
+        assert_json(
+            r#"'a' | test('a', :flags 'g')"#,
+            json!({"ERROR": ":flags keyword argument in test() must be a string of i, m, s or x"}),
+        );
+        assert_json(
+            r#"1 | test('a')"#,
+            json!({"ERROR": "test() must be called on a string"}),
+        );
+    }
+
+    #[test]
+    fn test_datetime() {
+        assert_json(
+            "['2024-03-10T12:34:56Z', '2024-03-10T12:34:56.250+02:00', '2024-03-10 12:34:56', '2024-03-10', 'Sun, 10 Mar 2024 12:34:56 +0000'] | map(to_datetime())",
+            json!([
+                "2024-03-10T12:34:56Z",
+                "2024-03-10T12:34:56.250+02:00",
+                "2024-03-10T12:34:56Z",
+                "2024-03-10T00:00:00Z",
+                "2024-03-10T12:34:56Z"
+            ]),
+        );
+        assert_json(
+            "[1710074096 | to_datetime(), 1710074096123 | to_datetime(:format 'unix_ms'), '10/03/2024 08:00' | to_datetime(:format '%d/%m/%Y %H:%M')]",
+            json!([
+                "2024-03-10T12:34:56Z",
+                "2024-03-10T12:34:56.123Z",
+                "2024-03-10T08:00:00Z"
+            ]),
+        );
+        assert_json(
+            "'2024-03-10T12:34:56Z' | to_datetime() | [format_datetime('%Y/%m/%d %H:%M'), to_timezone('America/New_York'), to_timezone('+05:30'), floor_time('15m'), floor_time('1d')]",
+            json!([
+                "2024/03/10 12:34",
+                "2024-03-10T08:34:56-04:00",
+                "2024-03-10T18:04:56+05:30",
+                "2024-03-10T12:30:00Z",
+                "2024-03-10T00:00:00Z"
+            ]),
+        );
+        assert_json(
+            "'2024-03-10T12:34:56Z' | to_datetime() | [. + 3600, . - 1.5, . - ('2024-03-10' | to_datetime())]",
+            json!(["2024-03-10T13:34:56Z", "2024-03-10T12:34:54.500Z", 45296]),
+        );
+        assert_json(
+            "'2024-03-09T12:00:00Z' | to_datetime() | to_timezone('America/New_York') | [. + 86400, . + 86400 - 86400, floor_time('1d') + 86400 | floor_time('1d'), to_timezone('+05:30') + 86400]",
+            json!([
+                "2024-03-10T08:00:00-04:00",
+                "2024-03-09T07:00:00-05:00",
+                "2024-03-10T00:00:00-05:00",
+                "2024-03-10T17:30:00+05:30"
+            ]),
+        );
+        assert_json(
+            "['2024-03-10T14:00:00+02:00', '2024-03-10T11:00:00Z'] | map(to_datetime()) | [.[0] == .[1], sort() | .[0], unique() | len()]",
+            json!([false, "2024-03-10T11:00:00Z", 2]),
+        );
+        assert_json(
+            "['2024-03-10T14:00:00+02:00', '2024-03-10T12:00:00Z'] | map(to_datetime()) | [.[0] == .[1], unique() | len()]",
+            json!([true, 1]),
+        );
+        assert_json(
+            "'junk' | to_datetime()",
+            json!({"ERROR": "to_datetime() unable to parse \"junk\" as a datetime"}),
+        );
+        assert_json(
+            "'2024-03-10' | to_datetime() | to_timezone('Nowhere/Else')",
+            json!({"ERROR": "to_timezone() unknown timezone \"Nowhere/Else\""}),
+        );
+        assert_json(
+            "('2024-03-10' | to_datetime()) * 2",
+            json!({"ERROR": "multiplication isn't supported for datetimes"}),
+        );
+    }
+
+    #[test]
+    fn test_durations() {
+        assert_json(
+            "['1h30m', '1h 30m', '250ms', '1.5h', 'P1DT2H', 'PT1.5S', 'P2W', '-90s', '90'] | map(parse_duration())",
+            json!([5400, 5400, 0.25, 5400, 93600, 1.5, 1209600, -90, 90]),
+        );
+        assert_json(
+            "['2h', '90m', '45s'] | map(parse_duration()) | [sum(), max()]",
+            json!([12645, 7200]),
+        );
+        assert_json(
+            "[5400, 0.25, 93600, 0, -90, 3600.5] | map(format_duration())",
+            json!(["1h30m", "250ms", "1d2h", "0s", "-1m30s", "1h0.5s"]),
+        );
+        assert_json(
+            "[5400, 0.25, 93600, 0, 86400] | map(format_duration(:style 'iso'))",
+            json!(["PT1H30M", "PT0.25S", "P1DT2H", "PT0S", "P1D"]),
+        );
+        assert_json(
+            "'P1Y' | parse_duration()",
+            json!({"ERROR": "parse_duration() unable to parse \"P1Y\" as a duration"}),
+        );
+        assert_json(
+            "1 | format_duration(:style 'long')",
+            json!({"ERROR": ":style keyword argument in format_duration() must be \"short\" or \"iso\""}),
+        );
+    }
+
+    #[test]
+    fn test_byte_sizes() {
+        assert_json(
+            "['1.5GiB', '10 MB', '512', '1k', '1KB', '0.1KiB', '3 bytes'] | map(parse_bytes())",
+            json!([1610612736, 10000000, 512, 1024, 1000, 102, 3]),
+        );
+        assert_json(
+            "[512, 1536, 1610612736, 1000] | map(format_bytes())",
+            json!(["512B", "1.5KiB", "1.5GiB", "1000B"]),
+        );
+        assert_json(
+            "[1000, 1500000] | map(format_bytes(:si true))",
+            json!(["1kB", "1.5MB"]),
+        );
+        assert_json(
+            "'lots' | parse_bytes()",
+            json!({"ERROR": "parse_bytes() unable to parse \"lots\" as a size"}),
+        );
+    }
+
     #[test]
     fn test_join() {
         assert_json(
@@ -642,6 +1021,7 @@ mod tests {
         assert_json("42 | repeat(0)", json!([]));
         assert_json("42 | repeat(1)", json!([42]));
         assert_json("42 | repeat(3)", json!([42, 42, 42]));
+        assert_json("42 | repeat(2 | decimal())", json!([42, 42]));
     }
 
     #[test]
@@ -656,6 +1036,141 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_from_csv() {
+        assert_json(
+            "'a,b\n1,x\n2.5,' | from_csv()",
+            json!([["a", "b"], ["1", "x"], ["2.5", ""]]),
+        );
+        assert_json(
+            "'a;b\n1;x\n2.5;\n-3;true' | from_csv(:header true, :delimiter ';', :infer_types true)",
+            json!([{"a": 1, "b": "x"}, {"a": 2.5, "b": null}, {"a": -3, "b": true}]),
+        );
+        assert_json(
+            "\"a,b\n'1,2',x\" | from_csv(:header true, :quote \"'\")",
+            json!([{"a": "1,2", "b": "x"}]),
+        );
+        assert_json(
+            "'a' | from_csv(:delimiter ';;')",
+            json!({"ERROR": ":delimiter keyword argument in from_csv() must be a single character"}),
+        );
+    }
+
+    #[test]
+    fn test_to_csv() {
+        assert_json(
+            "[[1, 'x,y'], [null, 2]] | to_csv() | str()",
+            json!("1,\"x,y\"\nnull,2\n"),
+        );
+        assert_json(
+            "[[1, 2], [3]] | to_csv()",
+            json!({"ERROR": "to_csv() must be called on rows of the same length"}),
+        );
+        assert_json(
+            "[{a: 1, b: 'x'}, {c: true, a: [1]}] | to_csv() | str()",
+            json!("a,b,c\n1,x,\n[1],,true\n"),
+        );
+        assert_json(
+            format!(
+                "[{{a: '{}' | read()}}] | to_csv() | str()",
+                non_utf8_file("csv")
+            )
+            .as_str(),
+            json!("a\n//5B\n"),
+        );
+        assert_json(
+            "[{a: 1, b: 'q\"'}] | to_csv() | from_csv(:header true, :infer_types true)",
+            json!([{"a": 1, "b": "q\""}]),
+        );
+        assert_json(
+            "[{a: 1}, [1]] | to_csv()",
+            json!({"ERROR": "to_csv() must be called on a list of lists or a list of maps"}),
+        );
+    }
+
+    #[test]
+    fn test_to_text_table() {
+        assert_json(
+            "[{name: 'a', n: 1}, {name: '日本', n: 22.5, x: [1]}] | to_text_table()",
+            json!("name     n  x\na        1\n日本  22.5  [1]\n"),
+        );
+        assert_json(
+            "[['h1', 'h2'], [1, 'x'], [22]] | to_text_table(:border true)",
+            json!("┌────┬────┐\n│ h1 │ h2 │\n├────┼────┤\n│  1 │ x  │\n│ 22 │    │\n└────┴────┘\n"),
+        );
+        assert_json(
+            "[{a: 'abcdef', b: 'c'}] | to_text_table(:max_width 4)",
+            json!("a     b\nabc…  c\n"),
+        );
+        assert_json("[] | to_text_table()", json!(""));
+        assert_json(
+            format!(
+                "[{{a: '{}' | read()}}] | to_text_table()",
+                non_utf8_file("text_table")
+            )
+            .as_str(),
+            json!("a\n//5B\n"),
+        );
+        assert_json(
+            "[1] | to_text_table()",
+            json!({"ERROR": "to_text_table() must be called on a list of maps or lists"}),
+        );
+    }
+
+    #[test]
+    fn test_to_markdown_table() {
+        assert_json(
+            "[{name: 'a|b', n: 1, x: {k: '*'}}, {name: 'a\nb_c', n: 2.5}] | to_markdown_table()",
+            json!(
+                "| name | n | x |\n| --- | ---: | --- |\n| a\\|b | 1 | {\"k\":\"\\*\"} |\n| a<br>b\\_c | 2.5 |  |\n"
+            ),
+        );
+        assert_json(
+            "[['a', 'b'], [1]] | to_markdown_table()",
+            json!("| a | b |\n| ---: | --- |\n| 1 |  |\n"),
+        );
+        assert_json(
+            format!(
+                "[{{a: '{}' | read()}}] | to_markdown_table()",
+                non_utf8_file("markdown_table")
+            )
+            .as_str(),
+            json!("| a |\n| --- |\n| //5B |\n"),
+        );
+    }
+
+    #[test]
+    fn test_to_html_table() {
+        assert_json(
+            "[{name: \"<it's & co>\", n: 1, x: [null]}] | to_html_table()",
+            json!(concat!(
+                "<table>\n  <thead>\n",
+                "    <tr><th>name</th><th align=\"right\">n</th><th>x</th></tr>\n",
+                "  </thead>\n  <tbody>\n",
+                "    <tr><td>&lt;it&#39;s &amp; co&gt;</td><td align=\"right\">1</td><td>[null]</td></tr>\n",
+                "  </tbody>\n</table>\n"
+            )),
+        );
+        assert_json(
+            format!(
+                "[{{a: '{}' | read()}}] | to_html_table()",
+                non_utf8_file("html_table")
+            )
+            .as_str(),
+            json!(concat!(
+                "<table>\n  <thead>\n",
+                "    <tr><th>a</th></tr>\n",
+                "  </thead>\n  <tbody>\n",
+                "    <tr><td>//5B</td></tr>\n",
+                "  </tbody>\n</table>\n"
+            )),
+        );
+        assert_json(
+            "'a' | to_html_table()",
+            json!({"ERROR": "to_html_table() must be called on a list"}),
+        );
+    }
+
     #[test]
     fn test_assign() {
         assert_json("100 | let a = 4 | . + a + 7", json!(111));
@@ -869,6 +1384,10 @@ mod tests {
             "[1, 'stuff', false, 2, 3.5] | filter(matches_type(%str))",
             json!(["stuff"]),
         );
+        assert_json(
+            "[%int, %str, %int, %[int], %[str]] | [unique() | len(), %int == %int, %int < %str, 1 < %int, %[int] < %[str]]",
+            json!([4, true, true, true, true]),
+        );
 
         assert_json(
             r#"
@@ -902,4 +1421,201 @@ mod tests {
             json!([1, 2, null]),
         );
     }
+
+    #[test]
+    fn test_with_variable() {
+        let ctx = EvalCtx::new()
+            .with_variable("a", Val::new_f64(1.0))
+            .with_variable("b", Val::new_str("two"));
+        let result = ctx.parse_and_eval("[a, b]").val;
+        let mut out = Vec::<u8>::new();
+        EvalCtx::write_val(&result, &mut out, false).unwrap();
+        assert_eq!(std::str::from_utf8(out.as_slice()).unwrap(), r#"[1,"two"]"#);
+    }
+
+    #[test]
+    fn test_ndjson() {
+        assert_json(
+            r#"'\{"a": 1\}\n\{"a": 2\}\n\n\{"a": 3\} [4]\n' | from_ndjson()"#,
+            json!([{"a": 1}, {"a": 2}, {"a": 3}, [4]]),
+        );
+        assert_json(r#"'' | from_ndjson()"#, json!([]));
+        assert_json(r#"'\{"a": ' | from_ndjson() | is_err()"#, json!(true));
+        assert_json(
+            r#"[{a: 1}, [2], "three"] | to_ndjson()"#,
+            json!("{\"a\":1}\n[2]\n\"three\"\n"),
+        );
+        assert_json(
+            r#"[{a: 1}, 2] | to_ndjson() | from_ndjson()"#,
+            json!([{"a": 1}, 2]),
+        );
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        assert_json(
+            "[1,\n  2",
+            json!({"ERROR": "Parse error at line 2, column 4: expected ']' to close the list opened here"}),
+        );
+        assert_json(
+            "[1, 2] | map(. + 1",
+            json!({"ERROR": "Parse error at line 1, column 19: expected ')' to close call to map( opened here"}),
+        );
+    }
+
+    #[test]
+    fn test_user_defined_functions() {
+        assert_json("def inc(x) = . + x; [1, 2] | map(inc(10))", json!([11, 12]));
+        assert_json("def two() = 2; two() * two()", json!(4));
+        assert_json(
+            "def fact(n) = if(n <= 1, 1, n * fact(n - 1)); fact(5)",
+            json!(120),
+        );
+        assert_json(
+            "def add(x, :by 1) = x + by; [add(1), add(1, :by 5), add(:x 2)]",
+            json!([2, 6, 3]),
+        );
+        assert_json(
+            "let k = 3 | def addk() = . + k; let k = 100 | 1 | addk()",
+            json!(4),
+        );
+        assert_json("def len() = \"mine\"; [1, 2] | len()", json!("mine"));
+        assert_json(
+            "def f(a, b) = [a, b]; [f(1), f(1, 2, 3), f(:c 1)]",
+            json!([
+                {"ERROR": "f() is missing the argument b"},
+                {"ERROR": "f() must be called with at most 2 arguments"},
+                {"ERROR": "Unknown keyword argument :c passed to f()"},
+            ]),
+        );
+        assert_json("let define = 1 | define", json!(1));
+        assert_json("let def = 2 | [def + 1, def * def]", json!([3, 4]));
+        assert_json("{def: 1} | .def", json!(1));
+    }
+
+    #[test]
+    fn test_completions() {
+        let ctx = EvalCtx::new()
+            .with_variable("config", Val::new_str("x"))
+            .parse_and_eval("def my_fcn() = 1; {metadata: {name: 1, namespace: 2, \"a b\": 3}}");
+
+        assert_eq!(
+            ctx.completions("map_", 4),
+            (0, vec!["map_keys(".to_string(), "map_values(".to_string()])
+        );
+        assert_eq!(
+            ctx.completions("[1] | my_", 9),
+            (6, vec!["my_fcn(".to_string()])
+        );
+        assert_eq!(ctx.completions("conf", 4), (0, vec!["config".to_string()]));
+        assert_eq!(ctx.completions(".", 1), (1, vec!["metadata".to_string()]));
+        assert_eq!(
+            ctx.completions(".metadata.na | len()", 12),
+            (10, vec!["name".to_string(), "namespace".to_string()])
+        );
+        assert_eq!(ctx.completions(".missing.na", 11), (9, vec![]));
+        assert_eq!(ctx.completions("1.5", 3), (2, vec![]));
+    }
+
+    #[test]
+    fn test_builtin_fcns_list_matches_dispatch() {
+        // BUILTIN_FCNS is written by hand, so check it against the match arms
+        // in eval_fcn() to keep completion and :help from drifting
+        let source = include_str!("../src/evaluator/eval_fcn.rs");
+        let quoted = |text: &str| {
+            text.split('"')
+                .skip(1)
+                .step_by(2)
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let list_start = source.find("pub const BUILTIN_FCNS").unwrap();
+        let list_end = list_start + source[list_start..].find("\n];").unwrap();
+        let mut listed = Vec::new();
+        let mut lines = source[list_start..list_end].lines().skip(1);
+        while let Some(line) = lines.next() {
+            // Long entries are split over lines with the name first
+            if line == "    (" {
+                listed.push(quoted(lines.next().unwrap())[0].clone());
+            } else if line.starts_with("    (\"") {
+                listed.push(quoted(line)[0].clone());
+            }
+        }
+
+        let match_start = source.find("pub fn eval_fcn(").unwrap();
+        let match_start = match_start + source[match_start..].find("match name {").unwrap();
+        let match_end = match_start
+            + source[match_start..]
+                .find("_ => Val::new_err(format!(\"Unknown function")
+                .unwrap();
+        let mut dispatched = Vec::new();
+        let mut in_pattern = false;
+        for line in source[match_start..match_end].lines() {
+            let arm = line.strip_prefix("            ").unwrap_or("");
+            if arm.starts_with('"') || (in_pattern && arm.starts_with("| \"")) {
+                let pattern = arm.split("=>").next().unwrap();
+                dispatched.extend(quoted(pattern));
+                in_pattern = !arm.contains("=>");
+            }
+        }
+
+        let mut listed_sorted = listed.clone();
+        listed_sorted.sort();
+        listed_sorted.dedup();
+        assert_eq!(listed_sorted.len(), listed.len(), "duplicate BUILTIN_FCNS");
+        // Arms with a guard repeat the name
+        dispatched.sort();
+        dispatched.dedup();
+        assert_eq!(listed_sorted, dispatched);
+    }
+
+    #[test]
+    fn test_preview() {
+        let budget = std::time::Duration::from_millis(200);
+        let ctx = EvalCtx::new().parse_and_eval("def inc(x) = . + x; [1, 2]");
+
+        let val = ctx.preview("map(inc(1))", budget).unwrap();
+        let mut out = Vec::<u8>::new();
+        EvalCtx::write_val(&val, &mut out, false).unwrap();
+        assert_eq!(out, b"[2,3]");
+
+        assert!(ctx.preview("map(", budget).is_none());
+        assert!(ctx.preview("in()", budget).is_none());
+        assert!(ctx
+            .preview("def f() = exec(\"ls\"); f() | catch(1)", budget)
+            .is_none());
+        assert!(ctx
+            .preview("range(1000000) | map(. * 2)", std::time::Duration::ZERO)
+            .is_none());
+        assert!(ctx.preview("range(100000000)", budget).is_none());
+        assert!(ctx.preview("[1, 2] | repeat(100000000)", budget).is_none());
+        assert!(ctx.preview("\"Cargo.toml\" | read()", budget).is_none());
+        assert!(ctx.preview("import \"lib.kj\" as lib; 1", budget).is_none());
+
+        let ctx = EvalCtx::new().parse_and_eval("range(200000)");
+        assert!(ctx.preview("sort()", budget).is_none());
+        assert!(ctx.preview("len()", budget).is_none());
+        assert!(ctx.preview("[1, 2]", budget).is_some());
+    }
+
+    #[test]
+    fn test_preview_recursion() {
+        // The REPL runs on a thread with a large stack, set up by main()
+        let preview = std::thread::Builder::new()
+            .stack_size(256 * 1024 * 1024)
+            .spawn(|| {
+                let budget = std::time::Duration::from_millis(200);
+                let val = EvalCtx::new()
+                    .preview("def f() = f(); f()", budget)
+                    .unwrap();
+                let mut out = Vec::<u8>::new();
+                EvalCtx::write_val(&val, &mut out, false).unwrap();
+                out
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(preview, b"{\"ERROR\":\"maximum recursion depth exceeded\"}");
+    }
 }